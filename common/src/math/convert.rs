@@ -0,0 +1,177 @@
+//! conversions from the fixed point types in this module to `f64` and `rust_decimal`,
+//! operating directly on the scaled integer representation rather than going through
+//! the types' string formatting, along with exact parsing of decimal strings
+
+use super::error::MathError;
+use super::uint::U256;
+
+/// the largest value representable by the 96 bit mantissa of a `rust_decimal::Decimal`
+const MAX_RUST_DECIMAL_MANTISSA: u128 = (1 << 96) - 1;
+
+/// converts `value / 10^scale` to the nearest f64. every value of a 256 bit integer is
+/// within the range of f64, so the conversion can't fail, but values beyond 2^53 lose
+/// precision
+pub(crate) fn scaled_to_f64(value: U256, scale: usize) -> f64 {
+    let (integer, fraction) = value.div_mod(U256::exp10(scale));
+    u256_to_f64(integer) + u256_to_f64(fraction) / 10_f64.powi(scale as i32)
+}
+
+/// converts `value / 10^scale` to a `rust_decimal::Decimal`. when the value doesn't
+/// fit within the decimal's 96 bit mantissa, digits are dropped from the fraction,
+/// rounding half up. returns `None` if the integer part itself doesn't fit
+pub(crate) fn scaled_to_rust_decimal(value: U256, scale: usize) -> Option<rust_decimal::Decimal> {
+    // rust_decimal supports at most 28 fractional digits, so at least `scale - 28`
+    // digits are dropped, and more while the value doesn't fit within the mantissa.
+    // the digits are always dropped from the original value and rounded once, as
+    // rounding a digit at a time rounds twice, for example 0.1449 -> 0.145 -> 0.15
+    let max = U256::from(MAX_RUST_DECIMAL_MANTISSA);
+    let mut dropped = scale.saturating_sub(28);
+    let mantissa = loop {
+        if dropped > scale {
+            return None;
+        }
+        let (quotient, remainder) = value.div_mod(U256::exp10(dropped));
+        let rounds_up = match dropped.checked_sub(1) {
+            // half of the divisor is 5 * 10^(dropped - 1)
+            Some(exponent) => remainder >= U256::from(5_u64) * U256::exp10(exponent),
+            None => false,
+        };
+        let rounded = if rounds_up {
+            quotient + U256::one()
+        } else {
+            quotient
+        };
+        if rounded <= max {
+            break rounded.as_u128();
+        }
+        dropped += 1;
+    };
+    Some(rust_decimal::Decimal::from_parts(
+        mantissa as u32,
+        (mantissa >> 32) as u32,
+        (mantissa >> 64) as u32,
+        false,
+        (scale - dropped) as u32,
+    ))
+}
+
+/// parses a decimal string such as `"1.5"` into its value scaled by `10^scale`. the
+/// string must have at least one integer digit, and at most `scale` fractional digits,
+/// so that the parsed value is exact rather than silently rounded
+pub(crate) fn parse_scaled(value: &str, scale: usize) -> Result<U256, MathError> {
+    let (integer, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let is_digits = |digits: &str| digits.bytes().all(|b| b.is_ascii_digit());
+    if integer.is_empty()
+        || !is_digits(integer)
+        || !is_digits(fraction)
+        || value.ends_with('.')
+        || fraction.len() > scale
+    {
+        return Err(MathError::InvalidDecimal);
+    }
+    let parse_digits = |digits: &str| {
+        digits.bytes().try_fold(U256::zero(), |acc, b| {
+            acc.checked_mul(U256::from(10_u64))?
+                .checked_add(U256::from(b - b'0'))
+        })
+    };
+    let integer = parse_digits(integer)
+        .and_then(|integer| integer.checked_mul(U256::exp10(scale)))
+        .ok_or(MathError::MathOverflow)?;
+    let fraction = parse_digits(fraction).ok_or(MathError::MathOverflow)?
+        * U256::exp10(scale - fraction.len());
+    integer.checked_add(fraction).ok_or(MathError::MathOverflow)
+}
+
+fn u256_to_f64(value: U256) -> f64 {
+    value.0.iter().rev().fold(0_f64, |acc, word| {
+        acc * 18_446_744_073_709_551_616_f64 + *word as f64
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_scaled_to_f64() {
+        assert_eq!(scaled_to_f64(U256::zero(), 18), 0_f64);
+        assert_eq!(scaled_to_f64(U256::exp10(18), 18), 1_f64);
+        assert_eq!(scaled_to_f64(U256::from(1_500_000_u64), 6), 1.5);
+        assert_eq!(scaled_to_f64(U256::from(1_u64), 18), 1e-18);
+        // values beyond u128 are still converted
+        let large = U256::exp10(60);
+        assert!((scaled_to_f64(large, 18) / 1e42 - 1_f64).abs() < 1e-15);
+        assert!(scaled_to_f64(U256::max_value(), 0).is_finite());
+    }
+
+    #[test]
+    fn test_scaled_to_rust_decimal() {
+        assert_eq!(
+            scaled_to_rust_decimal(U256::from(1_500_000_u64), 6).unwrap(),
+            rust_decimal::Decimal::from_str("1.5").unwrap()
+        );
+        assert_eq!(
+            scaled_to_rust_decimal(U256::from(123_456_789_u64), 18).unwrap(),
+            rust_decimal::Decimal::from_str("0.000000000123456789").unwrap()
+        );
+        // too many digits for the mantissa, so the fraction is rounded
+        let value = U256::from_dec_str("123456789012345678901234567896").unwrap();
+        assert_eq!(
+            scaled_to_rust_decimal(value, 18).unwrap(),
+            rust_decimal::Decimal::from_str("123456789012.34567890123456790").unwrap()
+        );
+        // dropped digits are rounded once, 1449e-30 rounds to 14e-28 rather than
+        // rounding to 145e-29, and then again to 15e-28
+        assert_eq!(
+            scaled_to_rust_decimal(U256::from(1449_u64), 30).unwrap(),
+            rust_decimal::Decimal::from_parts(14, 0, 0, false, 28)
+        );
+        assert_eq!(
+            scaled_to_rust_decimal(U256::from(1450_u64), 30).unwrap(),
+            rust_decimal::Decimal::from_parts(15, 0, 0, false, 28)
+        );
+        // the integer part doesn't fit
+        assert!(scaled_to_rust_decimal(U256::exp10(30), 0).is_none());
+        assert!(scaled_to_rust_decimal(U256::exp10(50), 18).is_none());
+    }
+
+    #[test]
+    fn test_parse_scaled() {
+        assert_eq!(parse_scaled("0", 18).unwrap(), U256::zero());
+        assert_eq!(parse_scaled("1", 18).unwrap(), U256::exp10(18));
+        assert_eq!(parse_scaled("1.5", 6).unwrap(), U256::from(1_500_000_u64));
+        assert_eq!(
+            parse_scaled("0.000000000000000001", 18).unwrap(),
+            U256::from(1_u64)
+        );
+        assert_eq!(
+            parse_scaled("123456789.123456789123456789", 18).unwrap(),
+            U256::from_dec_str("123456789123456789123456789").unwrap()
+        );
+        // more fractional digits than the scale can't be represented exactly
+        assert_eq!(
+            parse_scaled("0.0000000000000000001", 18),
+            Err(MathError::InvalidDecimal)
+        );
+        for invalid in [
+            "", ".", ".5", "1.", "-1", "+1", "1.2.3", "1e18", " 1", "1,5",
+        ] {
+            assert_eq!(
+                parse_scaled(invalid, 18),
+                Err(MathError::InvalidDecimal),
+                "{}",
+                invalid
+            );
+        }
+        assert_eq!(
+            parse_scaled(&"9".repeat(78), 0),
+            Err(MathError::MathOverflow)
+        );
+        assert_eq!(
+            parse_scaled(&"9".repeat(70), 18),
+            Err(MathError::MathOverflow)
+        );
+    }
+}
@@ -25,13 +25,15 @@
 #![allow(clippy::manual_range_contains)]
 
 use super::common::*;
+use super::convert::{parse_scaled, scaled_to_f64, scaled_to_rust_decimal};
 use super::error::*;
 use super::rate::Rate;
 
-use super::uint::U192;
+use super::uint::{U192, U256};
 
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use solana_program::program_error::ProgramError;
-use std::{convert::TryFrom, fmt};
+use std::{cmp::Ordering, convert::TryFrom, fmt, str::FromStr};
 
 /// Large decimal values, precise to 18 digits
 #[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, Eq, Ord)]
@@ -103,6 +105,34 @@ impl Decimal {
             .ok_or(MathError::MathOverflow)?;
         Ok(u64::try_from(ceil_val).map_err(|_| MathError::MathOverflow)?)
     }
+
+    /// Convert to the nearest f64, which can represent every decimal
+    pub fn to_f64(&self) -> f64 {
+        scaled_to_f64(self.to_u256(), SCALE)
+    }
+
+    /// Convert to a rust_decimal Decimal, rounding digits beyond its precision
+    pub fn try_to_rust_decimal(&self) -> Result<rust_decimal::Decimal, ProgramError> {
+        Ok(scaled_to_rust_decimal(self.to_u256(), SCALE).ok_or(MathError::MathOverflow)?)
+    }
+
+    /// Return true if the decimal is zero
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    /// Absolute difference between two decimals, which can't overflow
+    pub fn abs_diff(&self, other: Self) -> Self {
+        match self.cmp(&other) {
+            Ordering::Less => Self(other.0 - self.0),
+            _ => Self(self.0 - other.0),
+        }
+    }
+
+    fn to_u256(self) -> U256 {
+        let U192(words) = self.0;
+        U256([words[0], words[1], words[2], 0])
+    }
 }
 
 impl fmt::Display for Decimal {
@@ -118,6 +148,33 @@ impl fmt::Display for Decimal {
     }
 }
 
+/// Parses decimal strings as formatted by `Display`, such as `"1.5"`. Strings with
+/// more than 18 fractional digits are rejected rather than rounded
+impl FromStr for Decimal {
+    type Err = MathError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let U256(words) = parse_scaled(s, SCALE)?;
+        if words[3] != 0 {
+            return Err(MathError::MathOverflow);
+        }
+        Ok(Self(U192([words[0], words[1], words[2]])))
+    }
+}
+
+/// Serialized as a decimal string, so values are carried exactly
+impl Serialize for Decimal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        Self::from_str(&value).map_err(de::Error::custom)
+    }
+}
+
 impl From<u64> for Decimal {
     fn from(val: u64) -> Self {
         Self(Self::wad() * U192::from(val))
@@ -358,4 +415,82 @@ mod test {
         assert!(U192::from(WAD) == Decimal::wad());
         assert!(U192::from(HALF_WAD) == Decimal::half_wad());
     }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(Decimal::zero().to_f64(), 0_f64);
+        assert_eq!(Decimal::from_percent(69).to_f64(), 0.69);
+        assert_eq!(Decimal::from(u64::MAX).to_f64(), u64::MAX as f64);
+        assert!(Decimal(U192::MAX).to_f64().is_finite());
+        assert_eq!(
+            Decimal::from_percent(69).try_to_rust_decimal().unwrap(),
+            rust_decimal::Decimal::new(69, 2)
+        );
+        assert_eq!(
+            Decimal::from(1_000_000_u64).try_to_rust_decimal().unwrap(),
+            rust_decimal::Decimal::from(1_000_000_u64)
+        );
+        assert!(Decimal(U192::MAX).try_to_rust_decimal().is_err());
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(Decimal::from_str("0").unwrap(), Decimal::zero());
+        assert_eq!(Decimal::from_str("1").unwrap(), Decimal::one());
+        assert_eq!(
+            Decimal::from_str("0.69").unwrap(),
+            Decimal::from_percent(69)
+        );
+        assert_eq!(
+            Decimal::from_str("0.000000000000000001").unwrap(),
+            Decimal::from_scaled_val(1)
+        );
+        // display output round trips exactly
+        for decimal in [
+            Decimal::zero(),
+            Decimal::from_scaled_val(1),
+            Decimal::from_percent(253),
+            Decimal::from(u64::MAX),
+            Decimal(U192::MAX),
+        ] {
+            assert_eq!(Decimal::from_str(&decimal.to_string()).unwrap(), decimal);
+        }
+        assert_eq!(
+            Decimal::from_str("0.0000000000000000001"),
+            Err(MathError::InvalidDecimal)
+        );
+        assert_eq!(Decimal::from_str("-1"), Err(MathError::InvalidDecimal));
+        // an extra fractional digit is rejected as invalid, but an extra integer digit
+        // overflows the scaled value
+        let invalid = format!("{}0", Decimal(U192::MAX));
+        assert_eq!(Decimal::from_str(&invalid), Err(MathError::InvalidDecimal));
+        let overflow = format!("{}0", U192::MAX / U192::from(WAD));
+        assert_eq!(Decimal::from_str(&overflow), Err(MathError::MathOverflow));
+        // as do integers too large for the intermediate 256 bit value
+        let overflow = format!("1{}", "0".repeat(80));
+        assert_eq!(Decimal::from_str(&overflow), Err(MathError::MathOverflow));
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let decimal = Decimal::from_str("123456789.123456789123456789").unwrap();
+        let json = serde_json::to_string(&decimal).unwrap();
+        assert_eq!(json, "\"123456789.123456789123456789\"");
+        assert_eq!(serde_json::from_str::<Decimal>(&json).unwrap(), decimal);
+        assert!(serde_json::from_str::<Decimal>("\"1.5.0\"").is_err());
+        assert!(serde_json::from_str::<Decimal>("1.5").is_err());
+    }
+
+    #[test]
+    fn ordering() {
+        let a = Decimal::from_percent(69);
+        let b = Decimal::from_percent(212);
+        assert!(a < b);
+        assert_eq!(a.max(b), b);
+        assert_eq!(Decimal::from(5_u64).clamp(a, b), b);
+        assert_eq!(a.abs_diff(b), Decimal::from_percent(143));
+        assert_eq!(b.abs_diff(a), Decimal::from_percent(143));
+        assert!(Decimal::zero().is_zero());
+        assert!(!a.is_zero());
+    }
 }
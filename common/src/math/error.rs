@@ -9,6 +9,8 @@ use thiserror::Error;
 pub enum MathError {
     #[error("Math operation overflow")]
     MathOverflow,
+    #[error("Invalid decimal string")]
+    InvalidDecimal,
 }
 
 impl From<MathError> for ProgramError {
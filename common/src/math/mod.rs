@@ -6,6 +6,7 @@
 //! includes all math related types from the spl token lending program
 
 pub mod common;
+pub mod convert;
 pub mod decimal;
 pub mod error;
 pub mod precise;
@@ -1,5 +1,6 @@
 //! Defines PreciseNumber, a U256 wrapper with float-like operations
 
+use super::convert::{scaled_to_f64, scaled_to_rust_decimal};
 use super::uint::U256;
 
 // Allows for easy swapping between different internal representations
@@ -8,6 +9,27 @@ type InnerUint = U256;
 /// The representation of the number one as a precise number as 10^12
 pub const ONE: u128 = 1_000_000_000_000;
 
+/// The number of decimal digits of precision, such that ONE is 10^PRECISION_DIGITS
+const PRECISION_DIGITS: usize = 12;
+
+/// The scale of intermediate results in checked_ln, checked_exp and checked_powf as
+/// 10^30, carrying 18 more digits than ONE so rounding errors stay out of the result
+const INTERMEDIATE_ONE: u128 = 1_000_000_000_000_000_000_000_000_000_000;
+
+/// ln(2) scaled by INTERMEDIATE_ONE
+const LN_2: u128 = 693_147_180_559_945_309_417_232_121_458;
+
+/// ln(10) scaled by INTERMEDIATE_ONE
+const LN_10: u128 = 2_302_585_092_994_045_684_017_991_454_684;
+
+/// Exponents above this overflow in exp_intermediate, since e^200 doesn't fit a precise
+/// number
+const MAX_EXP_EXPONENT: u128 = 200;
+
+/// Negative exponents below minus this round to zero in exp_intermediate, since e^-64
+/// is far below 10^-12
+const MAX_NEGATIVE_EXP_EXPONENT: u128 = 64;
+
 /// Struct encapsulating a fixed-point number that allows for decimal calculations
 #[derive(Clone, Debug, PartialEq)]
 pub struct PreciseNumber {
@@ -73,6 +95,17 @@ impl PreciseNumber {
         Some(Self { value })
     }
 
+    /// Convert a precise number to the nearest f64, which can represent every precise number
+    pub fn to_f64(&self) -> f64 {
+        scaled_to_f64(self.value, PRECISION_DIGITS)
+    }
+
+    /// Convert a precise number to a rust_decimal Decimal, rounding digits beyond its
+    /// precision. returns `None` if the integer part doesn't fit
+    pub fn to_rust_decimal(&self) -> Option<rust_decimal::Decimal> {
+        scaled_to_rust_decimal(self.value, PRECISION_DIGITS)
+    }
+
     /// Convert a precise number back to u128
     pub fn to_imprecise(&self) -> Option<u128> {
         self.value
@@ -361,6 +394,152 @@ impl PreciseNumber {
         let guess = self.checked_add(&one)?.checked_div(&two)?;
         self.newtonian_root_approximation(&two, guess, Self::MAX_APPROXIMATION_ITERATIONS)
     }
+
+    /// Computes the natural logarithm, returning its magnitude and whether it is
+    /// negative, like unsigned_sub. The result is within one unit in the last place
+    /// (10^-12) of the exact value. Returns None for zero
+    pub fn checked_ln(&self) -> Option<(Self, bool)> {
+        let (value, negative) = Self::ln_intermediate(self.value)?;
+        let value = Self::round_intermediate(value)?;
+        let negative = negative && value != zero();
+        Some((Self { value }, negative))
+    }
+
+    /// Computes e^self, or e^-self when negative is set, so the sign returned by
+    /// checked_ln can be passed back in. The result is within one unit in the last
+    /// place (10^-12) plus a relative error of 10^-24 of the exact value. Returns None
+    /// if the result doesn't fit, which happens for exponents above roughly 149.8
+    pub fn checked_exp(&self, negative: bool) -> Option<Self> {
+        let exponent = match self.value.checked_mul(Self::intermediate_ratio()) {
+            Some(exponent) => exponent,
+            None if negative => return Some(Self::zero()),
+            None => return None,
+        };
+        let value = Self::exp_intermediate(exponent, negative)?;
+        Some(Self { value })
+    }
+
+    /// Raises self to a fractional power, computed as e^(exponent * ln(self)). Unlike
+    /// checked_pow_fraction, any base is supported. The result is within one unit in
+    /// the last place (10^-12) plus a relative error of (1 + exponent) * 10^-24 of the
+    /// exact value. Returns None if the result doesn't fit
+    pub fn checked_powf(&self, exponent: &Self) -> Option<Self> {
+        if self.value == zero() {
+            if exponent.value == zero() {
+                return Some(Self::one());
+            }
+            return Some(Self::zero());
+        }
+        let (ln, negative) = Self::ln_intermediate(self.value)?;
+        let product = match ln.checked_mul(exponent.value) {
+            Some(product) => product.checked_div(one())?,
+            None if negative => return Some(Self::zero()),
+            None => return None,
+        };
+        let value = Self::exp_intermediate(product, negative)?;
+        Some(Self { value })
+    }
+
+    /// The ratio between INTERMEDIATE_ONE and ONE
+    fn intermediate_ratio() -> InnerUint {
+        InnerUint::from(INTERMEDIATE_ONE / ONE)
+    }
+
+    /// Rounds an intermediate value to a precise value
+    fn round_intermediate(value: InnerUint) -> Option<InnerUint> {
+        let ratio = Self::intermediate_ratio();
+        value
+            .checked_add(ratio.checked_div(InnerUint::from(2))?)?
+            .checked_div(ratio)
+    }
+
+    /// Computes ln(value / ONE) scaled by INTERMEDIATE_ONE, along with whether it is
+    /// negative. The value is normalized to y * 2^k with 1 <= y < 2, so that
+    /// ln(value / ONE) = ln(y) + k * ln(2) - 12 * ln(10), where ln(y) is given by the
+    /// series 2 * (z + z^3 / 3 + z^5 / 5 + ...) with z = (y - 1) / (y + 1) < 1/3
+    fn ln_intermediate(value: InnerUint) -> Option<(InnerUint, bool)> {
+        if value == zero() {
+            return None;
+        }
+        let one = InnerUint::from(INTERMEDIATE_ONE);
+        // keep the top 128 bits of the value, so that y can be computed without
+        // overflowing while keeping far more precision than the result needs
+        let bits = value.bits();
+        let mantissa = if bits > 128 {
+            value >> (bits - 128)
+        } else {
+            value << (128 - bits)
+        };
+        let y = mantissa.checked_mul(one)? >> 127;
+        let z = y
+            .checked_sub(one)?
+            .checked_mul(one)?
+            .checked_div(y.checked_add(one)?)?;
+        let z_squared = z.checked_mul(z)?.checked_div(one)?;
+        let mut term = z;
+        let mut sum = z;
+        let mut denominator = 1_u64;
+        while term != zero() {
+            term = term.checked_mul(z_squared)?.checked_div(one)?;
+            denominator = denominator.checked_add(2)?;
+            sum = sum.checked_add(term.checked_div(InnerUint::from(denominator))?)?;
+        }
+        let positive = sum
+            .checked_mul(InnerUint::from(2))?
+            .checked_add(InnerUint::from(LN_2).checked_mul(InnerUint::from(bits - 1))?)?;
+        let negative = InnerUint::from(LN_10).checked_mul(InnerUint::from(PRECISION_DIGITS))?;
+        if positive >= negative {
+            Some((positive.checked_sub(negative)?, false))
+        } else {
+            Some((negative.checked_sub(positive)?, true))
+        }
+    }
+
+    /// Computes e^exponent, or e^-exponent when negative is set, for an exponent scaled
+    /// by INTERMEDIATE_ONE, returning a precise value. The exponent is reduced to
+    /// k * ln(2) + r with 0 <= r < ln(2), so that e^exponent = 2^k * e^r, where e^r is
+    /// given by the series 1 + r + r^2 / 2! + r^3 / 3! + ...
+    fn exp_intermediate(exponent: InnerUint, negative: bool) -> Option<InnerUint> {
+        let one = InnerUint::from(INTERMEDIATE_ONE);
+        if negative {
+            if exponent > one.checked_mul(InnerUint::from(MAX_NEGATIVE_EXP_EXPONENT))? {
+                return Some(zero());
+            }
+        } else if exponent > one.checked_mul(InnerUint::from(MAX_EXP_EXPONENT))? {
+            return None;
+        }
+        let ln_2 = InnerUint::from(LN_2);
+        let k = exponent.checked_div(ln_2)?;
+        let r = exponent.checked_sub(k.checked_mul(ln_2)?)?;
+        let k = k.as_usize();
+        let mut term = one;
+        let mut sum = one;
+        let mut n = 0_u64;
+        while term != zero() {
+            n = n.checked_add(1)?;
+            term = term
+                .checked_mul(r)?
+                .checked_div(one)?
+                .checked_div(InnerUint::from(n))?;
+            sum = sum.checked_add(term)?;
+        }
+        let ratio = Self::intermediate_ratio();
+        if negative {
+            // 1 / e^r lies within (1/2, 1], and k is at most 92 here, so the divisor
+            // can't overflow
+            let inverse = one.checked_mul(one)?.checked_div(sum)?;
+            let divisor = ratio.checked_mul(InnerUint::from(1) << k)?;
+            inverse
+                .checked_add(divisor.checked_div(InnerUint::from(2))?)?
+                .checked_div(divisor)
+        } else {
+            // e^r is below 2^101, so shifting by up to 150 bits can't overflow. the
+            // rest of the shift is applied after rounding away the intermediate digits
+            let shift = k.min(150);
+            let value = Self::round_intermediate(sum << shift)?;
+            value.checked_mul(InnerUint::from(1) << (k - shift))
+        }
+    }
 }
 
 #[cfg(test)]
@@ -368,6 +547,25 @@ mod tests {
     use super::*;
     use proptest::prelude::*;
 
+    #[test]
+    fn test_conversions() {
+        let half = PreciseNumber::new(1)
+            .unwrap()
+            .checked_div(&PreciseNumber::new(2).unwrap())
+            .unwrap();
+        assert_eq!(half.to_f64(), 0.5);
+        assert_eq!(
+            half.to_rust_decimal().unwrap(),
+            rust_decimal::Decimal::new(5, 1)
+        );
+        let max = PreciseNumber {
+            value: InnerUint::max_value(),
+        };
+        assert!(max.to_f64().is_finite());
+        assert!(max.to_rust_decimal().is_none());
+        assert_eq!(PRECISION_DIGITS, ONE.to_string().len() - 1);
+    }
+
     fn check_pow_approximation(base: InnerUint, exponent: InnerUint, expected: InnerUint) {
         let precision = InnerUint::from(5_000_000); // correct to at least 3 decimal places
         let base = PreciseNumber { value: base };
@@ -559,11 +757,313 @@ mod tests {
         assert_eq!(whole_number.value, ceiling_again.value);
     }
 
+    /// Asserts that a result is within one unit in the last place plus a relative error
+    /// of 1 / relative_divisor of the expected value, which is rounded to ONE
+    fn check_close(result: &PreciseNumber, expected: InnerUint, relative_divisor: InnerUint) {
+        let expected = PreciseNumber { value: expected };
+        let (difference, _) = result.unsigned_sub(&expected);
+        let tolerance = InnerUint::from(1) + expected.value / relative_divisor;
+        assert!(
+            difference.value <= tolerance,
+            "{} differs from {} by {}",
+            result.value,
+            expected.value,
+            difference.value
+        );
+    }
+
+    /// An independent reference for ln, exp and powf, computed with 512 bit integers
+    /// scaled by 10^50. It uses different range reductions than PreciseNumber, and
+    /// carries 38 more digits than ONE, so its own error is far below the tolerances
+    mod reference {
+        #![allow(clippy::assign_op_pattern)]
+        #![allow(clippy::ptr_offset_with_cast)]
+        #![allow(clippy::manual_range_contains)]
+
+        use super::ONE;
+        use uint::construct_uint;
+
+        construct_uint! {
+            pub struct U512(8);
+        }
+
+        /// e scaled by 10^50, computed with 120 digits of precision
+        const E: &str = "271828182845904523536028747135266249775724709369996";
+
+        /// ln(10) scaled by 10^50, computed with 120 digits of precision
+        const LN_10: &str = "230258509299404568401799145468436420760110148862877";
+
+        /// The scale of reference values, 10^50
+        pub fn scale() -> U512 {
+            U512::exp10(50)
+        }
+
+        fn mul(a: U512, b: U512) -> U512 {
+            a * b / scale()
+        }
+
+        fn div(a: U512, b: U512) -> U512 {
+            a * scale() / b
+        }
+
+        /// Computes ln(value / ONE), along with whether it is negative. The value is
+        /// reduced to x * 10^k with 1 <= x < 10, which is exact since value has fewer
+        /// digits than the scale, and ln(x) is given by 2 * atanh((x - 1) / (x + 1))
+        pub fn ln(value: u128) -> (U512, bool) {
+            let one = scale();
+            let ten = U512::from(10);
+            let mut x = U512::from(value) * one / U512::from(ONE);
+            let mut k = 0_i32;
+            while x >= one * ten {
+                x /= ten;
+                k += 1;
+            }
+            while x < one {
+                x *= ten;
+                k -= 1;
+            }
+            let z = div(x - one, x + one);
+            let z_squared = mul(z, z);
+            let mut term = z;
+            let mut sum = z;
+            let mut denominator = 1_u64;
+            while !term.is_zero() {
+                term = mul(term, z_squared);
+                denominator += 2;
+                sum += term / U512::from(denominator);
+            }
+            let ln_x = sum * U512::from(2);
+            let ln_10 = U512::from_dec_str(LN_10).unwrap() * U512::from(k.unsigned_abs());
+            if k >= 0 {
+                (ln_x + ln_10, false)
+            } else if ln_x >= ln_10 {
+                (ln_x - ln_10, false)
+            } else {
+                (ln_10 - ln_x, true)
+            }
+        }
+
+        /// Computes e^exponent, or e^-exponent when negative is set. The exponent is
+        /// split into its integer part n and fraction f, where e^n is computed by repeated
+        /// multiplication, and e^f by the series 1 + f + f^2 / 2! + ...
+        pub fn exp(exponent: U512, negative: bool) -> U512 {
+            let one = scale();
+            let (n, f) = exponent.div_mod(one);
+            // e^-120 is below the precision of the reference
+            if negative && n >= U512::from(120) {
+                return U512::zero();
+            }
+            let e = U512::from_dec_str(E).unwrap();
+            let mut power = one;
+            for _ in 0..n.as_u64() {
+                power = mul(power, e);
+            }
+            let mut term = one;
+            let mut sum = one;
+            let mut i = 0_u64;
+            while !term.is_zero() {
+                i += 1;
+                term = mul(term, f) / U512::from(i);
+                sum += term;
+            }
+            let value = mul(power, sum);
+            if negative {
+                div(one, value)
+            } else {
+                value
+            }
+        }
+
+        /// Computes (base / ONE)^(exponent / ONE) as e^(exponent * ln(base))
+        pub fn powf(base: u128, exponent: u128) -> U512 {
+            let (ln, negative) = ln(base);
+            exp(ln * U512::from(exponent) / U512::from(ONE), negative)
+        }
+    }
+
+    /// Asserts that a signed result is within one unit in the last place, plus a relative
+    /// error of relative_units * 10^-24, of a signed reference value
+    fn check_reference(
+        result: &PreciseNumber,
+        negative: bool,
+        expected: (reference::U512, bool),
+        relative_units: u128,
+    ) {
+        let words = result.value.0;
+        let ulp = reference::scale() / reference::U512::from(ONE);
+        let value = reference::U512([words[0], words[1], words[2], words[3], 0, 0, 0, 0]) * ulp;
+        let (expected, expected_negative) = expected;
+        let difference = if negative == expected_negative {
+            if value > expected {
+                value - expected
+            } else {
+                expected - value
+            }
+        } else {
+            value + expected
+        };
+        let tolerance =
+            ulp + expected * reference::U512::from(relative_units) / reference::U512::exp10(24);
+        assert!(
+            difference <= tolerance,
+            "{} differs from the reference {} by {}",
+            result.value,
+            expected,
+            difference
+        );
+    }
+
+    #[test]
+    fn test_ln() {
+        let one = one();
+        // reference values were computed with 100 digits of precision, and rounded to ONE
+        let cases = [
+            (one * 2, 693_147_180_560_u128, false),
+            (one * 10, 2_302_585_092_994, false),
+            (one / 2, 693_147_180_560, true),
+            (one + 1, 1, false),
+            (one, 0, false),
+            (InnerUint::from(1), 27_631_021_115_929, true),
+            (InnerUint::from(u128::MAX), 61_091_817_995_744, false),
+            (InnerUint::max_value(), 149_814_657_107_417, false),
+        ];
+        for (value, expected, expected_negative) in cases {
+            let (ln, negative) = PreciseNumber { value }.checked_ln().unwrap();
+            assert_eq!(ln.value, InnerUint::from(expected));
+            assert_eq!(negative, expected_negative);
+        }
+        assert!(PreciseNumber::zero().checked_ln().is_none());
+    }
+
+    #[test]
+    fn test_exp() {
+        let one = one();
+        let relative_divisor = InnerUint::exp10(24);
+        let cases = [
+            (zero(), false, one),
+            (one, false, InnerUint::from(2_718_281_828_459_u128)),
+            (one / 2, false, InnerUint::from(1_648_721_270_700_u128)),
+            (
+                one * 10,
+                false,
+                InnerUint::from(22_026_465_794_806_717_u128),
+            ),
+            (
+                one * 100,
+                false,
+                InnerUint::from_dec_str("26881171418161354484126255515800135873611118773741922415")
+                    .unwrap(),
+            ),
+            (one, true, InnerUint::from(367_879_441_171_u128)),
+            (one * 27, true, InnerUint::from(2)),
+            (one * 28, true, InnerUint::from(1)),
+            (one * 1_000, true, zero()),
+            (InnerUint::max_value(), true, zero()),
+        ];
+        for (value, negative, expected) in cases {
+            let exp = PreciseNumber { value }.checked_exp(negative).unwrap();
+            check_close(&exp, expected, relative_divisor);
+        }
+        // e^150 * 10^12 is above 2^256
+        assert!(PreciseNumber::new(150)
+            .unwrap()
+            .checked_exp(false)
+            .is_none());
+        assert!(PreciseNumber::new(149)
+            .unwrap()
+            .checked_exp(false)
+            .is_some());
+        // exp reverses ln
+        let value = PreciseNumber::new(42).unwrap();
+        let (ln, negative) = value.checked_ln().unwrap();
+        let exp = ln.checked_exp(negative).unwrap();
+        assert!(exp.almost_eq(&value, InnerUint::from(100)));
+    }
+
+    #[test]
+    fn test_powf() {
+        let one = one();
+        let cases = [
+            (one * 2, one / 2, InnerUint::from(1_414_213_562_373_u128)),
+            (
+                one * 105 / 100,
+                one * 365,
+                InnerUint::from(54_211_841_577_839_524_993_u128),
+            ),
+            (one / 2, one * 21 / 2, InnerUint::from(690_533_966_u128)),
+            (
+                one * 1_000,
+                one * 33 / 10,
+                InnerUint::from(7_943_282_347_242_815_020_659_u128),
+            ),
+            // daily compounding of a 5% apr, with the daily rate rounded to ONE
+            (
+                InnerUint::from(1_000_136_986_301_u128),
+                one * 365,
+                InnerUint::from(1_051_267_496_326_u128),
+            ),
+            (one, one * 123, one),
+            (zero(), zero(), one),
+            (zero(), one / 2, zero()),
+            (one / 2, InnerUint::exp10(40), zero()),
+        ];
+        for (base, exponent, expected) in cases {
+            let base = PreciseNumber { value: base };
+            let exponent = PreciseNumber { value: exponent };
+            let power = base.checked_powf(&exponent).unwrap();
+            // the relative error bound grows with the exponent
+            let relative_divisor =
+                (InnerUint::exp10(24) / (exponent.value / one + 1)).max(InnerUint::from(1));
+            check_close(&power, expected, relative_divisor);
+        }
+        let two = PreciseNumber::new(2).unwrap();
+        assert!(two
+            .checked_powf(&PreciseNumber::new(300).unwrap())
+            .is_none());
+    }
+
     proptest! {
         #[test]
         fn test_square_root(a in 0..u128::MAX) {
             let a = PreciseNumber { value: InnerUint::from(a) };
             check_square_root(&a);
         }
+
+        #[test]
+        fn test_ln_matches_reference(a in 1..u128::MAX) {
+            let (ln, negative) = PreciseNumber { value: InnerUint::from(a) }
+                .checked_ln()
+                .unwrap();
+            check_reference(&ln, negative, reference::ln(a), 0);
+        }
+
+        #[test]
+        fn test_exp_matches_reference(a in 0..100 * ONE, negative in any::<bool>()) {
+            let exp = PreciseNumber { value: InnerUint::from(a) }
+                .checked_exp(negative)
+                .unwrap();
+            let exponent = reference::U512::from(a) * reference::scale()
+                / reference::U512::from(ONE);
+            check_reference(&exp, false, (reference::exp(exponent, negative), false), 1);
+        }
+
+        #[test]
+        fn test_powf_matches_reference(base in 1..1_000 * ONE, exponent in 0..10 * ONE) {
+            let power = PreciseNumber { value: InnerUint::from(base) }
+                .checked_powf(&PreciseNumber { value: InnerUint::from(exponent) })
+                .unwrap();
+            // the relative error bound grows with the exponent
+            let expected = (reference::powf(base, exponent), false);
+            check_reference(&power, false, expected, exponent / ONE + 1);
+        }
+
+        #[test]
+        fn test_powf_identity(a in 1..u128::MAX) {
+            // x^1 goes through ln and exp at the intermediate precision, so it
+            // reproduces x within the documented error bound
+            let value = PreciseNumber { value: InnerUint::from(a) };
+            let power = value.checked_powf(&PreciseNumber::one()).unwrap();
+            check_close(&power, value.value, InnerUint::exp10(24) / 2);
+        }
     }
 }
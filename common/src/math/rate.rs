@@ -31,11 +31,14 @@
 #![allow(clippy::manual_range_contains)]
 
 use super::common::*;
+use super::convert::{parse_scaled, scaled_to_f64, scaled_to_rust_decimal};
 use super::decimal::Decimal;
 use super::error::*;
+use super::uint::U256;
 
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use solana_program::program_error::ProgramError;
-use std::{convert::TryFrom, fmt};
+use std::{cmp::Ordering, convert::TryFrom, fmt, str::FromStr};
 use uint::construct_uint;
 
 // U128 with 128 bits consisting of 2 x 64-bit words
@@ -106,6 +109,29 @@ impl Rate {
             .ok_or(MathError::MathOverflow)?;
         Ok(u64::try_from(ceil_val).map_err(|_| MathError::MathOverflow)?)
     }
+    /// Convert to the nearest f64, which can represent every rate
+    pub fn to_f64(&self) -> f64 {
+        scaled_to_f64(self.to_u256(), SCALE)
+    }
+    /// Convert to a rust_decimal Decimal, rounding digits beyond its precision
+    pub fn try_to_rust_decimal(&self) -> Result<rust_decimal::Decimal, ProgramError> {
+        Ok(scaled_to_rust_decimal(self.to_u256(), SCALE).ok_or(MathError::MathOverflow)?)
+    }
+    /// Return true if the rate is zero
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+    /// Absolute difference between two rates, which can't overflow
+    pub fn abs_diff(&self, other: Self) -> Self {
+        match self.cmp(&other) {
+            Ordering::Less => Self(other.0 - self.0),
+            _ => Self(self.0 - other.0),
+        }
+    }
+    fn to_u256(self) -> U256 {
+        let U128(words) = self.0;
+        U256([words[0], words[1], 0, 0])
+    }
     /// Calculates base^exp
     pub fn try_pow(&self, mut exp: u64) -> Result<Rate, ProgramError> {
         let mut base = *self;
@@ -141,6 +167,33 @@ impl fmt::Display for Rate {
     }
 }
 
+/// Parses decimal strings as formatted by `Display`, such as `"0.05"`. Strings with
+/// more than 18 fractional digits are rejected rather than rounded
+impl FromStr for Rate {
+    type Err = MathError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let U256(words) = parse_scaled(s, SCALE)?;
+        if words[2] != 0 || words[3] != 0 {
+            return Err(MathError::MathOverflow);
+        }
+        Ok(Self(U128([words[0], words[1]])))
+    }
+}
+
+/// Serialized as a decimal string, so values are carried exactly
+impl Serialize for Rate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        Self::from_str(&value).map_err(de::Error::custom)
+    }
+}
+
 impl TryFrom<Decimal> for Rate {
     type Error = ProgramError;
     fn try_from(decimal: Decimal) -> Result<Self, Self::Error> {
@@ -217,6 +270,55 @@ mod test {
         assert_eq!(Rate::one(), Rate::one().try_pow(u64::MAX).unwrap());
     }
 
+    #[test]
+    fn conversions() {
+        assert_eq!(Rate::from_percent(69).to_f64(), 0.69);
+        assert_eq!(Rate::from_scaled_val(1_u64).to_f64(), 1e-18);
+        assert!(Rate(U128::MAX).to_f64().is_finite());
+        assert_eq!(
+            Rate::from_percent(69).try_to_rust_decimal().unwrap(),
+            rust_decimal::Decimal::new(69, 2)
+        );
+        // every rate fits within a rust_decimal
+        assert!(Rate(U128::MAX).try_to_rust_decimal().is_ok());
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(Rate::from_str("1").unwrap(), Rate::one());
+        assert_eq!(Rate::from_str("0.05").unwrap(), Rate::from_percent(5));
+        for rate in [Rate::zero(), Rate::from_scaled_val(1), Rate(U128::MAX)] {
+            assert_eq!(Rate::from_str(&rate.to_string()).unwrap(), rate);
+        }
+        assert_eq!(
+            Rate::from_str("0.0000000000000000001"),
+            Err(MathError::InvalidDecimal)
+        );
+        // fits a decimal, but not a rate
+        let overflow = Decimal::from(u128::MAX).to_string();
+        assert!(Decimal::from_str(&overflow).is_ok());
+        assert_eq!(Rate::from_str(&overflow), Err(MathError::MathOverflow));
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let rate = Rate::from_str("0.123456789123456789").unwrap();
+        let json = serde_json::to_string(&rate).unwrap();
+        assert_eq!(json, "\"0.123456789123456789\"");
+        assert_eq!(serde_json::from_str::<Rate>(&json).unwrap(), rate);
+        assert!(serde_json::from_str::<Rate>("\"abc\"").is_err());
+    }
+
+    #[test]
+    fn ordering() {
+        let a = Rate::from_percent(5);
+        let b = Rate::from_percent(7);
+        assert_eq!(a.min(b), a);
+        assert_eq!(a.abs_diff(b), Rate::from_percent(2));
+        assert_eq!(b.abs_diff(a), Rate::from_percent(2));
+        assert!(Rate::zero().is_zero());
+    }
+
     #[test]
     fn rate() {
         assert_eq!(Rate::one(), Rate(Rate::wad()));
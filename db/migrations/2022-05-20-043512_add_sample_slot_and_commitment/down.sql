@@ -0,0 +1,10 @@
+ALTER TABLE interest_rate DROP COLUMN slot;
+ALTER TABLE token_price DROP COLUMN slot;
+ALTER TABLE vault_tvl DROP COLUMN slot;
+ALTER TABLE v1_obligation_ltv DROP COLUMN slot;
+ALTER TABLE token_balance DROP COLUMN slot;
+ALTER TABLE interest_rate DROP COLUMN commitment;
+ALTER TABLE token_price DROP COLUMN commitment;
+ALTER TABLE vault_tvl DROP COLUMN commitment;
+ALTER TABLE v1_obligation_ltv DROP COLUMN commitment;
+ALTER TABLE token_balance DROP COLUMN commitment;
@@ -0,0 +1,13 @@
+-- the slot the account data backing each sample was observed at, as reported
+-- by the rpc response context. samples recorded before this was tracked use 0
+ALTER TABLE interest_rate ADD COLUMN slot BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE token_price ADD COLUMN slot BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE vault_tvl ADD COLUMN slot BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE v1_obligation_ltv ADD COLUMN slot BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE token_balance ADD COLUMN slot BIGINT NOT NULL DEFAULT 0;
+-- the commitment level the account data was requested with
+ALTER TABLE interest_rate ADD COLUMN commitment VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE token_price ADD COLUMN commitment VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE vault_tvl ADD COLUMN commitment VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE v1_obligation_ltv ADD COLUMN commitment VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE token_balance ADD COLUMN commitment VARCHAR NOT NULL DEFAULT '';
@@ -0,0 +1,2 @@
+ALTER TABLE vault DROP COLUMN data_hash;
+ALTER TABLE v1_user_farm DROP COLUMN data_hash;
@@ -0,0 +1,4 @@
+-- hash of the content written by the last put, used to skip rewriting unchanged
+-- accounts. rows written before this was tracked use '' and are rewritten once
+ALTER TABLE vault ADD COLUMN data_hash VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE v1_user_farm ADD COLUMN data_hash VARCHAR NOT NULL DEFAULT '';
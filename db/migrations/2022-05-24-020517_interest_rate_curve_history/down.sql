@@ -0,0 +1 @@
+DROP TABLE interest_rate_curve_history;
@@ -0,0 +1,17 @@
+-- every distinct set of curve parameters observed for a reserve, written
+-- whenever the reserve config scraper sees a platform change its curve
+CREATE TABLE interest_rate_curve_history (
+    id BIGSERIAL PRIMARY KEY,
+    platform VARCHAR NOT NULL,
+    asset VARCHAR NOT NULL,
+    rate_name VARCHAR NOT NULL,
+    min_borrow_rate FLOAT8 NOT NULL,
+    max_borrow_rate FLOAT8 NOT NULL,
+    optimal_borrow_rate FLOAT8 NOT NULL,
+    optimal_utilization_rate FLOAT8 NOT NULL,
+    degen_borrow_rate FLOAT8 NOT NULL,
+    degen_utilization_rate FLOAT8 NOT NULL,
+    recorded_at TIMESTAMPTZ NOT NULL,
+    slot BIGINT NOT NULL DEFAULT 0
+);
+CREATE INDEX interest_rate_curve_history_rate_name_idx ON interest_rate_curve_history (rate_name, recorded_at);
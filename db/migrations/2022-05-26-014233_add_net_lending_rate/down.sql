@@ -0,0 +1 @@
+ALTER TABLE interest_rate DROP COLUMN net_lending_rate;
@@ -0,0 +1,4 @@
+-- the lending rate after protocol take rates. rows written before this was
+-- tracked use the gross lending rate
+ALTER TABLE interest_rate ADD COLUMN net_lending_rate DOUBLE PRECISION NOT NULL DEFAULT 0;
+UPDATE interest_rate SET net_lending_rate = lending_rate;
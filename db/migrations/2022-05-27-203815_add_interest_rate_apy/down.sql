@@ -0,0 +1,3 @@
+ALTER TABLE interest_rate DROP COLUMN borrow_apy;
+ALTER TABLE interest_rate DROP COLUMN lending_apy;
+ALTER TABLE interest_rate DROP COLUMN net_lending_apy;
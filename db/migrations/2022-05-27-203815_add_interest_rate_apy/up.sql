@@ -0,0 +1,5 @@
+-- compounded equivalents of the borrow_rate, lending_rate and net_lending_rate aprs.
+-- rows written before these were tracked use 0
+ALTER TABLE interest_rate ADD COLUMN borrow_apy DOUBLE PRECISION NOT NULL DEFAULT 0;
+ALTER TABLE interest_rate ADD COLUMN lending_apy DOUBLE PRECISION NOT NULL DEFAULT 0;
+ALTER TABLE interest_rate ADD COLUMN net_lending_apy DOUBLE PRECISION NOT NULL DEFAULT 0;
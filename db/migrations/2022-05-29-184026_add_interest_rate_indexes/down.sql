@@ -0,0 +1,3 @@
+DROP INDEX interest_rate_platform_asset_scraped_at_idx;
+ALTER TABLE interest_rate DROP COLUMN cumulative_borrow_rate;
+ALTER TABLE interest_rate DROP COLUMN collateral_exchange_rate;
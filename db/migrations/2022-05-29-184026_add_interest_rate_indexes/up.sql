@@ -0,0 +1,6 @@
+-- the reserve's cumulative borrow rate index, and collateral exchange rate, used to
+-- compute realized rates between samples. rows written before these were tracked,
+-- and platforms without them, use 0
+ALTER TABLE interest_rate ADD COLUMN cumulative_borrow_rate DOUBLE PRECISION NOT NULL DEFAULT 0;
+ALTER TABLE interest_rate ADD COLUMN collateral_exchange_rate DOUBLE PRECISION NOT NULL DEFAULT 0;
+CREATE INDEX interest_rate_platform_asset_scraped_at_idx ON interest_rate (platform, asset, scraped_at);
@@ -28,6 +28,8 @@ impl Default for VaultTvl {
             total_underlying: 0_f64,
             value_locked: 0_f64,
             scraped_at: *CURRENT_TIME,
+            slot: 0,
+            commitment: String::default(),
         }
     }
 }
@@ -42,6 +44,7 @@ impl Default for Vault {
             scraped_at: *CURRENT_TIME,
             last_compound_ts: None,
             last_compound_ts_unix: 0,
+            data_hash: String::default(),
         }
     }
 }
@@ -63,6 +66,8 @@ impl Default for TokenPrice {
             last_period_average: 0_f64,
             feed_stopped: false,
             token_mint: String::default(),
+            slot: 0,
+            commitment: String::default(),
         }
     }
 }
@@ -95,6 +100,14 @@ impl Default for InterestRate {
             available_amount: 0_f64,
             borrowed_amount: 0_f64,
             scraped_at: *CURRENT_TIME,
+            slot: 0,
+            commitment: String::default(),
+            net_lending_rate: 0_f64,
+            borrow_apy: 0_f64,
+            lending_apy: 0_f64,
+            net_lending_apy: 0_f64,
+            cumulative_borrow_rate: 0_f64,
+            collateral_exchange_rate: 0_f64,
         }
     }
 }
@@ -108,6 +121,8 @@ impl Default for TokenBalance {
             identifier: String::default(),
             balance: 0_f64,
             scraped_at: *CURRENT_TIME,
+            slot: 0,
+            commitment: String::default(),
         }
     }
 }
@@ -159,6 +174,25 @@ impl Default for InterestRateCurve {
     }
 }
 
+impl Default for InterestRateCurveHistory {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            platform: "".to_string(),
+            asset: "".to_string(),
+            rate_name: "".to_string(),
+            min_borrow_rate: 0_f64,
+            max_borrow_rate: 0_f64,
+            optimal_borrow_rate: 0_f64,
+            optimal_utilization_rate: 0_f64,
+            degen_borrow_rate: 0_f64,
+            degen_utilization_rate: 0_f64,
+            recorded_at: *CURRENT_TIME,
+            slot: 0,
+        }
+    }
+}
+
 impl Default for LendingOptimizerDistribution {
     fn default() -> Self {
         Self {
@@ -208,6 +242,8 @@ impl Default for V1ObligationLtv {
             ltv: 0_f64,
             scraped_at: *CURRENT_TIME,
             leveraged_farm: "".to_string(),
+            slot: 0,
+            commitment: "".to_string(),
         }
     }
 }
@@ -221,6 +257,7 @@ impl Default for V1UserFarm {
             obligations: vec![],
             obligation_indexes: vec![],
             leveraged_farm: "".to_string(),
+            data_hash: "".to_string(),
         }
     }
 }
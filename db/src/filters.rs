@@ -55,6 +55,14 @@ pub struct FindInterestRateCurve {
     pub rate_name: Option<Vec<String>>,
 }
 
+#[derive(IntoQuery, Default)]
+#[table_name = "interest_rate_curve_history"]
+pub struct FindInterestRateCurveHistory {
+    pub platform: Option<Vec<String>>,
+    pub asset: Option<Vec<String>>,
+    pub rate_name: Option<Vec<String>>,
+}
+
 #[derive(IntoQuery, Default)]
 #[table_name = "staking_analytic"]
 pub struct FindStakingAnalytic;
@@ -472,6 +480,15 @@ impl InterestRateCurveMatcher {
         }
         ft
     }
+    /// returns an instance of the filter matching the same curves in the history table
+    pub fn to_history_filter(&self) -> FindInterestRateCurveHistory {
+        let ft = self.to_filter();
+        FindInterestRateCurveHistory {
+            platform: ft.platform,
+            asset: ft.asset,
+            rate_name: ft.rate_name,
+        }
+    }
 }
 
 impl InterestRateMovingAverageMatcher {
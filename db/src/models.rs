@@ -54,6 +54,19 @@ pub struct InterestRate {
     pub available_amount: f64,
     pub borrowed_amount: f64,
     pub scraped_at: DateTime<Utc>,
+    pub slot: i64,
+    pub commitment: String,
+    /// the lending rate after protocol take rates, `lending_rate` is the gross rate
+    pub net_lending_rate: f64,
+    /// `borrow_rate`, `lending_rate` and `net_lending_rate` are aprs, these are
+    /// the same rates compounded according to the platform's compounding model
+    pub borrow_apy: f64,
+    pub lending_apy: f64,
+    pub net_lending_apy: f64,
+    /// the reserve's cumulative borrow rate index, 0 for platforms without one
+    pub cumulative_borrow_rate: f64,
+    /// collateral tokens per unit of liquidity, 0 for platforms without collateral tokens
+    pub collateral_exchange_rate: f64,
 }
 
 #[derive(Queryable, Debug, Identifiable, AsChangeset, Model, Clone)]
@@ -71,6 +84,23 @@ pub struct InterestRateCurve {
     pub degen_utilization_rate: f64,
 }
 
+#[derive(Queryable, Debug, Identifiable, AsChangeset, Model, Clone)]
+#[table_name = "interest_rate_curve_history"]
+pub struct InterestRateCurveHistory {
+    pub id: i64,
+    pub platform: String,
+    pub asset: String,
+    pub rate_name: String,
+    pub min_borrow_rate: f64,
+    pub max_borrow_rate: f64,
+    pub optimal_borrow_rate: f64,
+    pub optimal_utilization_rate: f64,
+    pub degen_borrow_rate: f64,
+    pub degen_utilization_rate: f64,
+    pub recorded_at: DateTime<Utc>,
+    pub slot: i64,
+}
+
 #[derive(Queryable, Debug, Identifiable, AsChangeset, Model, Clone)]
 #[table_name = "interest_rate_moving_average"]
 pub struct InterestRateMovingAverage {
@@ -129,6 +159,8 @@ pub struct TokenBalance {
     pub identifier: String,
     pub balance: f64,
     pub scraped_at: DateTime<Utc>,
+    pub slot: i64,
+    pub commitment: String,
 }
 
 #[derive(Queryable, Debug, Identifiable, AsChangeset, Model, Clone)]
@@ -148,6 +180,8 @@ pub struct TokenPrice {
     pub last_period_average: f64,
     pub feed_stopped: bool,
     pub token_mint: String,
+    pub slot: i64,
+    pub commitment: String,
 }
 
 #[derive(Queryable, Debug, Identifiable, AsChangeset, Model, Clone)]
@@ -182,6 +216,8 @@ pub struct V1ObligationLtv {
     pub ltv: f64,
     pub scraped_at: DateTime<Utc>,
     pub leveraged_farm: String,
+    pub slot: i64,
+    pub commitment: String,
 }
 
 #[derive(Queryable, Debug, Identifiable, AsChangeset, Model, Clone)]
@@ -193,6 +229,7 @@ pub struct V1UserFarm {
     pub obligations: Vec<String>,
     pub obligation_indexes: Vec<i32>,
     pub leveraged_farm: String,
+    pub data_hash: String,
 }
 
 #[derive(Queryable, Debug, Identifiable, AsChangeset, Model, Clone)]
@@ -205,6 +242,7 @@ pub struct Vault {
     pub scraped_at: DateTime<Utc>,
     pub last_compound_ts: Option<DateTime<Utc>>,
     pub last_compound_ts_unix: i64,
+    pub data_hash: String,
 }
 
 #[derive(Queryable, Debug, Identifiable, AsChangeset, Model, Clone)]
@@ -216,4 +254,6 @@ pub struct VaultTvl {
     pub total_underlying: f64,
     pub value_locked: f64,
     pub scraped_at: DateTime<Utc>,
+    pub slot: i64,
+    pub commitment: String,
 }
@@ -52,6 +52,14 @@ table! {
         available_amount -> Float8,
         borrowed_amount -> Float8,
         scraped_at -> Timestamptz,
+        slot -> Int8,
+        commitment -> Varchar,
+        net_lending_rate -> Float8,
+        borrow_apy -> Float8,
+        lending_apy -> Float8,
+        net_lending_apy -> Float8,
+        cumulative_borrow_rate -> Float8,
+        collateral_exchange_rate -> Float8,
     }
 }
 
@@ -72,6 +80,25 @@ table! {
     }
 }
 
+table! {
+    use diesel::sql_types::*;
+
+    interest_rate_curve_history (id) {
+        id -> Int8,
+        platform -> Varchar,
+        asset -> Varchar,
+        rate_name -> Varchar,
+        min_borrow_rate -> Float8,
+        max_borrow_rate -> Float8,
+        optimal_borrow_rate -> Float8,
+        optimal_utilization_rate -> Float8,
+        degen_borrow_rate -> Float8,
+        degen_utilization_rate -> Float8,
+        recorded_at -> Timestamptz,
+        slot -> Int8,
+    }
+}
+
 table! {
     use diesel::sql_types::*;
 
@@ -139,6 +166,8 @@ table! {
         identifier -> Varchar,
         balance -> Float8,
         scraped_at -> Timestamptz,
+        slot -> Int8,
+        commitment -> Varchar,
     }
 }
 
@@ -160,6 +189,8 @@ table! {
         last_period_average -> Float8,
         feed_stopped -> Bool,
         token_mint -> Varchar,
+        slot -> Int8,
+        commitment -> Varchar,
     }
 }
 
@@ -200,6 +231,8 @@ table! {
         ltv -> Float8,
         scraped_at -> Timestamptz,
         leveraged_farm -> Varchar,
+        slot -> Int8,
+        commitment -> Varchar,
     }
 }
 
@@ -213,6 +246,7 @@ table! {
         obligations -> Array<Text>,
         obligation_indexes -> Array<Int4>,
         leveraged_farm -> Varchar,
+        data_hash -> Varchar,
     }
 }
 
@@ -227,6 +261,7 @@ table! {
         scraped_at -> Timestamptz,
         last_compound_ts -> Nullable<Timestamptz>,
         last_compound_ts_unix -> Int8,
+        data_hash -> Varchar,
     }
 }
 
@@ -240,6 +275,8 @@ table! {
         total_underlying -> Float8,
         value_locked -> Float8,
         scraped_at -> Timestamptz,
+        slot -> Int8,
+        commitment -> Varchar,
     }
 }
 
@@ -249,6 +286,7 @@ allow_tables_to_appear_in_same_query!(
     historic_tshare_price,
     interest_rate,
     interest_rate_curve,
+    interest_rate_curve_history,
     interest_rate_moving_average,
     lending_optimizer_distribution,
     realize_yield,
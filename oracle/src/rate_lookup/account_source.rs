@@ -0,0 +1,472 @@
+//! provides the `AccountSource` trait which abstracts over how account data is retrieved,
+//! allowing lookups and scrapers to run against a live rpc node, or be replayed offline
+//! against a snapshot of account data previously recorded to disk
+
+use anchor_client::solana_client::nonblocking::rpc_client::RpcClient;
+use anchor_client::solana_client::rpc_config::RpcProgramAccountsConfig;
+use anchor_client::solana_client::rpc_filter::RpcFilterType;
+use anchor_client::solana_client::rpc_request::RpcRequest;
+use anchor_client::solana_client::rpc_response::{OptionalContext, RpcKeyedAccount};
+use anchor_client::solana_sdk::account::Account;
+use anchor_client::solana_sdk::commitment_config::CommitmentConfig;
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use solana_account_decoder::{UiAccount, UiAccountEncoding, UiDataSliceConfig};
+
+use crate::rate_lookup::metrics;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// name of the directory within a snapshot that account data is stored in
+pub const SNAPSHOT_ACCOUNTS_DIR: &str = "accounts";
+/// name of the file within a snapshot that snapshot metadata is stored in
+pub const SNAPSHOT_METADATA_FILE: &str = "metadata.json";
+
+/// a source of on-chain account data
+#[async_trait]
+pub trait AccountSource: Send + Sync {
+    /// returns the commitment level accounts are read at
+    fn commitment(&self) -> CommitmentConfig;
+    /// returns the given account, or None if it does not exist
+    async fn get_account(&self, key: &Pubkey) -> Result<Option<Account>>;
+    /// returns the given accounts in the order they were requested, along with
+    /// the slot the accounts were observed at
+    async fn get_multiple_accounts(&self, keys: &[Pubkey]) -> Result<(u64, Vec<Option<Account>>)>;
+    /// returns all accounts owned by `program_id` which match the filters in `config`,
+    /// along with the slot the accounts were observed at
+    async fn get_program_accounts(
+        &self,
+        program_id: &Pubkey,
+        config: RpcProgramAccountsConfig,
+    ) -> Result<(u64, Vec<(Pubkey, Account)>)>;
+    /// returns the given accounts as observed by up to `count` independent backends, which
+    /// allows reads to be cross checked. sources with a single backend return one response
+    async fn get_multiple_accounts_quorum(
+        &self,
+        keys: &[Pubkey],
+        _count: usize,
+    ) -> Result<Vec<(u64, Vec<Option<Account>>)>> {
+        Ok(vec![self.get_multiple_accounts(keys).await?])
+    }
+}
+
+/// an account source backed by the nonblocking rpc client, using the
+/// commitment level the client was configured with
+pub struct RpcAccountSource {
+    pub rpc: Arc<RpcClient>,
+}
+
+impl RpcAccountSource {
+    pub fn new(rpc: Arc<RpcClient>) -> Self {
+        Self { rpc }
+    }
+}
+
+#[async_trait]
+impl AccountSource for RpcAccountSource {
+    fn commitment(&self) -> CommitmentConfig {
+        self.rpc.commitment()
+    }
+    async fn get_account(&self, key: &Pubkey) -> Result<Option<Account>> {
+        let start = Instant::now();
+        let response = self
+            .rpc
+            .get_account_with_commitment(key, self.rpc.commitment())
+            .await;
+        metrics::observe_rpc_latency("getAccountInfo", start);
+        Ok(response?.value)
+    }
+    async fn get_multiple_accounts(&self, keys: &[Pubkey]) -> Result<(u64, Vec<Option<Account>>)> {
+        let start = Instant::now();
+        let response = self
+            .rpc
+            .get_multiple_accounts_with_commitment(keys, self.rpc.commitment())
+            .await;
+        metrics::observe_rpc_latency("getMultipleAccounts", start);
+        let response = response?;
+        Ok((response.context.slot, response.value))
+    }
+    async fn get_program_accounts(
+        &self,
+        program_id: &Pubkey,
+        mut config: RpcProgramAccountsConfig,
+    ) -> Result<(u64, Vec<(Pubkey, Account)>)> {
+        // `get_program_accounts_with_config` discards the response context, so
+        // the request is sent directly to learn the slot the accounts were read at
+        config.with_context = Some(true);
+        if config.account_config.commitment.is_none() {
+            config.account_config.commitment = Some(self.rpc.commitment());
+        }
+        let start = Instant::now();
+        let response = self
+            .rpc
+            .send::<OptionalContext<Vec<RpcKeyedAccount>>>(
+                RpcRequest::GetProgramAccounts,
+                json!([program_id.to_string(), config]),
+            )
+            .await;
+        metrics::observe_rpc_latency("getProgramAccounts", start);
+        let (slot, keyed_accounts) = match response? {
+            OptionalContext::Context(response) => (response.context.slot, response.value),
+            // nodes which don't support `with_context` return the accounts on their own,
+            // without the slot callers rely on to order scans against other reads
+            OptionalContext::NoContext(_) => {
+                return Err(anyhow!(
+                    "getProgramAccounts response for {} is missing the context slot",
+                    program_id
+                ))
+            }
+        };
+        let accounts = keyed_accounts
+            .into_iter()
+            .map(|keyed_account| {
+                let key = Pubkey::from_str(&keyed_account.pubkey)?;
+                match keyed_account.account.decode::<Account>() {
+                    Some(account) => Ok((key, account)),
+                    None => Err(anyhow!("failed to decode account {}", key)),
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok((slot, accounts))
+    }
+}
+
+/// metadata stored alongside a recorded snapshot
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotMetadata {
+    /// the most recent slot observed while recording the snapshot
+    pub slot: u64,
+    /// the commitment level the snapshot was recorded at
+    #[serde(default)]
+    pub commitment: CommitmentConfig,
+}
+
+/// an account source which serves account data from a snapshot directory
+/// previously written by `RecordingAccountSource`.
+///
+/// the snapshot directory contains a `metadata.json` file, and an `accounts`
+/// directory containing one json encoded `UiAccount` per account, named `<pubkey>.json`
+#[derive(Default, Debug, Clone)]
+pub struct SnapshotAccountSource {
+    pub slot: u64,
+    pub commitment: CommitmentConfig,
+    pub accounts: HashMap<Pubkey, Account>,
+}
+
+impl SnapshotAccountSource {
+    /// loads all accounts from the snapshot stored at `dir`
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let metadata: SnapshotMetadata =
+            serde_json::from_slice(&fs::read(dir.join(SNAPSHOT_METADATA_FILE))?)?;
+        let mut accounts = HashMap::new();
+        for entry in fs::read_dir(dir.join(SNAPSHOT_ACCOUNTS_DIR))? {
+            let path = entry?.path();
+            let key = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(stem) => Pubkey::from_str(stem)?,
+                None => return Err(anyhow!("invalid snapshot file {}", path.display())),
+            };
+            accounts.insert(key, read_account(&path)?);
+        }
+        Ok(Self {
+            slot: metadata.slot,
+            commitment: metadata.commitment,
+            accounts,
+        })
+    }
+}
+
+#[async_trait]
+impl AccountSource for SnapshotAccountSource {
+    fn commitment(&self) -> CommitmentConfig {
+        self.commitment
+    }
+    async fn get_account(&self, key: &Pubkey) -> Result<Option<Account>> {
+        Ok(self.accounts.get(key).cloned())
+    }
+    async fn get_multiple_accounts(&self, keys: &[Pubkey]) -> Result<(u64, Vec<Option<Account>>)> {
+        Ok((
+            self.slot,
+            keys.iter()
+                .map(|key| self.accounts.get(key).cloned())
+                .collect(),
+        ))
+    }
+    async fn get_program_accounts(
+        &self,
+        program_id: &Pubkey,
+        config: RpcProgramAccountsConfig,
+    ) -> Result<(u64, Vec<(Pubkey, Account)>)> {
+        let filters = config.filters.unwrap_or_default();
+        let mut accounts: Vec<(Pubkey, Account)> = self
+            .accounts
+            .iter()
+            .filter(|(_, account)| account.owner.eq(program_id))
+            .filter(|(_, account)| filters_match(&filters, &account.data))
+            .map(|(key, account)| {
+                (
+                    *key,
+                    slice_account(account, config.account_config.data_slice),
+                )
+            })
+            .collect();
+        // hashmap iteration order is random, so sort to keep replays deterministic
+        accounts.sort_unstable_by_key(|(key, _)| *key);
+        Ok((self.slot, accounts))
+    }
+}
+
+/// wraps another account source, writing every account it returns into a
+/// snapshot directory which can later be replayed with `SnapshotAccountSource`
+pub struct RecordingAccountSource<S: AccountSource> {
+    pub inner: S,
+    dir: PathBuf,
+    slot: AtomicU64,
+    /// serializes writes to the snapshot directory
+    lock: Mutex<()>,
+}
+
+impl<S: AccountSource> RecordingAccountSource<S> {
+    /// creates a recording source which writes into `dir`, creating it if needed
+    pub fn new(inner: S, dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(dir.join(SNAPSHOT_ACCOUNTS_DIR))?;
+        let recorder = Self {
+            inner,
+            dir,
+            slot: AtomicU64::new(0),
+            lock: Mutex::new(()),
+        };
+        recorder.write_metadata()?;
+        Ok(recorder)
+    }
+    fn record(&self, key: &Pubkey, account: &Account) -> Result<()> {
+        let _guard = self
+            .lock
+            .lock()
+            .map_err(|_| anyhow!("snapshot lock poisoned"))?;
+        let ui_account = UiAccount::encode(key, account, UiAccountEncoding::Base64, None, None);
+        fs::write(
+            self.dir
+                .join(SNAPSHOT_ACCOUNTS_DIR)
+                .join(format!("{}.json", key)),
+            serde_json::to_vec(&ui_account)?,
+        )?;
+        Ok(())
+    }
+    fn record_slot(&self, slot: u64) -> Result<()> {
+        if self.slot.fetch_max(slot, Ordering::SeqCst) < slot {
+            self.write_metadata()?;
+        }
+        Ok(())
+    }
+    fn write_metadata(&self) -> Result<()> {
+        let _guard = self
+            .lock
+            .lock()
+            .map_err(|_| anyhow!("snapshot lock poisoned"))?;
+        fs::write(
+            self.dir.join(SNAPSHOT_METADATA_FILE),
+            serde_json::to_vec(&SnapshotMetadata {
+                slot: self.slot.load(Ordering::SeqCst),
+                commitment: self.inner.commitment(),
+            })?,
+        )?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<S: AccountSource> AccountSource for RecordingAccountSource<S> {
+    fn commitment(&self) -> CommitmentConfig {
+        self.inner.commitment()
+    }
+    async fn get_account(&self, key: &Pubkey) -> Result<Option<Account>> {
+        let account = self.inner.get_account(key).await?;
+        if let Some(account) = &account {
+            self.record(key, account)?;
+        }
+        Ok(account)
+    }
+    async fn get_multiple_accounts(&self, keys: &[Pubkey]) -> Result<(u64, Vec<Option<Account>>)> {
+        let (slot, accounts) = self.inner.get_multiple_accounts(keys).await?;
+        for (key, account) in keys.iter().zip(accounts.iter()) {
+            if let Some(account) = account {
+                self.record(key, account)?;
+            }
+        }
+        self.record_slot(slot)?;
+        Ok((slot, accounts))
+    }
+    async fn get_program_accounts(
+        &self,
+        program_id: &Pubkey,
+        mut config: RpcProgramAccountsConfig,
+    ) -> Result<(u64, Vec<(Pubkey, Account)>)> {
+        // always record the full account data so that the snapshot can serve
+        // any data slice requested during replay, slicing before returning
+        let data_slice = config.account_config.data_slice.take();
+        let (slot, accounts) = self.inner.get_program_accounts(program_id, config).await?;
+        for (key, account) in accounts.iter() {
+            self.record(key, account)?;
+        }
+        self.record_slot(slot)?;
+        Ok((
+            slot,
+            accounts
+                .into_iter()
+                .map(|(key, account)| (key, slice_account(&account, data_slice)))
+                .collect(),
+        ))
+    }
+}
+
+/// returns true if the account data matches all of the given filters
+pub fn filters_match(filters: &[RpcFilterType], data: &[u8]) -> bool {
+    filters.iter().all(|filter| match filter {
+        RpcFilterType::DataSize(size) => data.len() as u64 == *size,
+        RpcFilterType::Memcmp(memcmp) => memcmp.bytes_match(data),
+    })
+}
+
+/// returns a copy of the account with its data restricted to `data_slice`
+pub fn slice_account(account: &Account, data_slice: Option<UiDataSliceConfig>) -> Account {
+    let data = match data_slice {
+        Some(slice) => {
+            let start = slice.offset.min(account.data.len());
+            let end = slice
+                .offset
+                .saturating_add(slice.length)
+                .min(account.data.len());
+            account.data[start..end].to_vec()
+        }
+        None => account.data.clone(),
+    };
+    Account {
+        lamports: account.lamports,
+        data,
+        owner: account.owner,
+        executable: account.executable,
+        rent_epoch: account.rent_epoch,
+    }
+}
+
+fn read_account(path: &Path) -> Result<Account> {
+    let ui_account: UiAccount = serde_json::from_slice(&fs::read(path)?)?;
+    match ui_account.decode::<Account>() {
+        Some(account) => Ok(account),
+        None => Err(anyhow!("failed to decode account {}", path.display())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anchor_client::solana_client::rpc_config::RpcAccountInfoConfig;
+    use anchor_client::solana_client::rpc_filter::{Memcmp, MemcmpEncodedBytes};
+
+    fn test_account(owner: Pubkey, data: Vec<u8>) -> Account {
+        Account {
+            lamports: 1_000_000,
+            data,
+            owner,
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay_snapshot() {
+        let program_id = Pubkey::new_unique();
+        let mut source = SnapshotAccountSource {
+            slot: 420,
+            commitment: CommitmentConfig::confirmed(),
+            ..Default::default()
+        };
+        let key_1 = Pubkey::new_unique();
+        let key_2 = Pubkey::new_unique();
+        let key_3 = Pubkey::new_unique();
+        source
+            .accounts
+            .insert(key_1, test_account(program_id, vec![1, 2, 3, 4]));
+        source
+            .accounts
+            .insert(key_2, test_account(program_id, vec![1, 9, 9]));
+        source
+            .accounts
+            .insert(key_3, test_account(Pubkey::new_unique(), vec![1, 2, 3, 4]));
+
+        let dir = std::env::temp_dir().join(format!("account_source_{}", Pubkey::new_unique()));
+        let recorder = RecordingAccountSource::new(source, &dir).unwrap();
+        let (slot, accounts) = recorder
+            .get_multiple_accounts(&[key_1, key_3, Pubkey::new_unique()])
+            .await
+            .unwrap();
+        assert_eq!(slot, 420);
+        assert!(accounts[0].is_some());
+        assert!(accounts[1].is_some());
+        assert!(accounts[2].is_none());
+        let config = RpcProgramAccountsConfig {
+            filters: Some(vec![RpcFilterType::Memcmp(Memcmp {
+                offset: 1,
+                bytes: MemcmpEncodedBytes::Bytes(vec![9]),
+                encoding: None,
+            })]),
+            with_context: None,
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                data_slice: Some(UiDataSliceConfig {
+                    offset: 0,
+                    length: 1,
+                }),
+                commitment: None,
+            },
+        };
+        let (slot, accounts) = recorder
+            .get_program_accounts(&program_id, config.clone())
+            .await
+            .unwrap();
+        assert_eq!(slot, 420);
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].0, key_2);
+        assert_eq!(accounts[0].1.data, vec![1]);
+
+        let replay = SnapshotAccountSource::load(&dir).unwrap();
+        assert_eq!(replay.slot, 420);
+        assert_eq!(replay.commitment, CommitmentConfig::confirmed());
+        assert_eq!(replay.accounts.len(), 3);
+        assert_eq!(
+            replay.get_account(&key_1).await.unwrap().unwrap().data,
+            vec![1, 2, 3, 4]
+        );
+        let (_, accounts) = replay
+            .get_program_accounts(&program_id, config)
+            .await
+            .unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].0, key_2);
+        assert_eq!(accounts[0].1.data, vec![1]);
+        let (_, accounts) = replay
+            .get_program_accounts(
+                &program_id,
+                RpcProgramAccountsConfig {
+                    filters: Some(vec![RpcFilterType::DataSize(4)]),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].0, key_1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
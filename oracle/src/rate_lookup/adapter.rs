@@ -0,0 +1,543 @@
+//! provides the `LendingReserveAdapter` trait, which exposes the state of a lending
+//! platform's reserve in a common format. supporting a new lending platform only
+//! requires implementing the trait for its reserve type
+
+use anyhow::{anyhow, Result};
+use az::CheckedCast;
+use common::mango::mango_lib::state::{RootBank, ZERO_I80F48};
+use common::mango::mango_lib::utils::compute_deposit_rate;
+use common::math::decimal::Decimal;
+use common::math::rate::Rate;
+use common::port::port_variable_rate_lending_instructions::state as port_state;
+use common::solend::solend_token_lending::state as solend_state;
+use common::tulip as tulip_state;
+use common::tulip::lending_reserve::CollateralExchangeRate;
+use common::tulip::LENDING_SLOTS_PER_YEAR;
+use fixed::types::I80F48;
+
+/// mango accrues interest per second elapsed since the last index update
+pub const MANGO_SECONDS_PER_YEAR: u64 = 31_536_000;
+
+/// the state of a lending reserve. amounts are in native units of the reserve's
+/// token, and rates are fractions, so a borrow rate of 0.1 is 10%
+pub trait LendingReserveAdapter {
+    /// name of the platform, used to label errors
+    fn platform(&self) -> &'static str;
+    /// liquidity available to borrow
+    fn available_amount(&self) -> Result<u64>;
+    /// liquidity currently borrowed
+    fn borrowed_amount(&self) -> Result<u64>;
+    /// decimals of the reserve's token
+    fn decimals(&self) -> u8;
+    fn utilization_rate(&self) -> Result<f64>;
+    fn borrow_rate(&self) -> Result<f64>;
+    /// the rate earned by suppliers, by default the borrow rate paid
+    /// on the utilized portion of the reserve
+    fn supply_rate(&self) -> Result<f64> {
+        Ok(self.borrow_rate()? * self.utilization_rate()?)
+    }
+    /// the fraction of borrow interest kept by the protocol instead of being
+    /// paid to suppliers. origination fees are charged on top of the borrowed
+    /// amount, so they don't reduce the supply rate
+    fn protocol_take_rate(&self) -> Result<f64> {
+        Ok(0_f64)
+    }
+    /// the rate earned by suppliers after the protocol's take rate
+    fn net_supply_rate(&self) -> Result<f64> {
+        Ok(self.supply_rate()? * (1_f64 - self.protocol_take_rate()?))
+    }
+    /// the number of times per year interest is compounded, by default once
+    /// per slot as spl style reserves accrue interest per slot elapsed
+    fn compounding_periods_per_year(&self) -> u64 {
+        LENDING_SLOTS_PER_YEAR
+    }
+    /// the slot interest was last accrued at, `None` for reserves which
+    /// don't accrue interest per slot
+    fn last_update_slot(&self) -> Option<u64> {
+        None
+    }
+    /// accrues interest up to `current_slot`, as the program does when the reserve
+    /// is refreshed. callers should only accrue on a copy of the reserve
+    fn accrue_interest(&mut self, _current_slot: u64) -> Result<()> {
+        Ok(())
+    }
+    /// the reserve's cumulative borrow rate index, which grows by the interest
+    /// accrued on borrows. `None` for reserves which don't track one
+    fn cumulative_borrow_rate(&self) -> Result<Option<f64>> {
+        Ok(None)
+    }
+    /// the number of collateral tokens minted per unit of liquidity deposited, which
+    /// falls as interest is paid to suppliers. `None` for reserves without collateral
+    fn collateral_exchange_rate(&self) -> Result<Option<f64>> {
+        Ok(None)
+    }
+}
+
+/// accrues interest on `reserve` up to `current_slot`, returning the number of slots
+/// the reserve had gone without accruing interest, and whether interest was accrued.
+/// reserves observed after `current_slot`, as happens when reading from a backend
+/// ahead of the one the slot was fetched from, are left as is
+pub fn accrue_to_slot(
+    reserve: &mut dyn LendingReserveAdapter,
+    current_slot: u64,
+) -> Result<(u64, bool)> {
+    let last_update_slot = match reserve.last_update_slot() {
+        Some(slot) => slot,
+        None => return Ok((0, false)),
+    };
+    if current_slot <= last_update_slot {
+        return Ok((0, false));
+    }
+    reserve.accrue_interest(current_slot)?;
+    Ok((current_slot - last_update_slot, true))
+}
+
+/// converts an apr into an apy compounded `periods_per_year` times per year
+pub fn apr_to_apy(apr: f64, periods_per_year: u64) -> f64 {
+    if periods_per_year == 0 {
+        return apr;
+    }
+    let periods = periods_per_year as f64;
+    // equivalent to (1 + apr / periods)^periods - 1, without losing precision
+    // to the tiny per period rate
+    ((apr / periods).ln_1p() * periods).exp_m1()
+}
+
+impl LendingReserveAdapter for tulip_state::lending_reserve::Reserve {
+    fn platform(&self) -> &'static str {
+        "tulip"
+    }
+    fn available_amount(&self) -> Result<u64> {
+        Ok(self.liquidity.available_amount)
+    }
+    fn borrowed_amount(&self) -> Result<u64> {
+        Ok(self.liquidity.borrowed_amount_wads.try_floor_u64()?)
+    }
+    fn decimals(&self) -> u8 {
+        self.liquidity.mint_decimals
+    }
+    fn utilization_rate(&self) -> Result<f64> {
+        Ok(self.liquidity.utilization_rate()?.to_f64())
+    }
+    fn borrow_rate(&self) -> Result<f64> {
+        Ok(self.current_borrow_rate()?.to_f64())
+    }
+    fn last_update_slot(&self) -> Option<u64> {
+        Some(self.last_update.slot)
+    }
+    fn accrue_interest(&mut self, current_slot: u64) -> Result<()> {
+        Ok(Self::accrue_interest(self, current_slot)?)
+    }
+    fn cumulative_borrow_rate(&self) -> Result<Option<f64>> {
+        Ok(Some(self.liquidity.cumulative_borrow_rate_wads.to_f64()))
+    }
+    fn collateral_exchange_rate(&self) -> Result<Option<f64>> {
+        Ok(Some(
+            Rate::from(Self::collateral_exchange_rate(self)?).to_f64(),
+        ))
+    }
+    /// tulip credits `platform_fees` percent of accrued interest to the platform
+    fn protocol_take_rate(&self) -> Result<f64> {
+        Ok(self.liquidity.platform_fees as f64 / 100_f64)
+    }
+}
+
+impl LendingReserveAdapter for solend_state::Reserve {
+    fn platform(&self) -> &'static str {
+        "solend"
+    }
+    fn available_amount(&self) -> Result<u64> {
+        Ok(self.liquidity.available_amount)
+    }
+    fn borrowed_amount(&self) -> Result<u64> {
+        Ok(self.liquidity.borrowed_amount_wads.try_floor_u64()?)
+    }
+    fn decimals(&self) -> u8 {
+        self.liquidity.mint_decimals
+    }
+    fn utilization_rate(&self) -> Result<f64> {
+        Ok(scaled_rate_to_f64(
+            self.liquidity.utilization_rate()?.to_scaled_val(),
+        ))
+    }
+    fn borrow_rate(&self) -> Result<f64> {
+        Ok(scaled_rate_to_f64(
+            self.current_borrow_rate()?.to_scaled_val(),
+        ))
+    }
+    fn last_update_slot(&self) -> Option<u64> {
+        Some(self.last_update.slot)
+    }
+    fn accrue_interest(&mut self, current_slot: u64) -> Result<()> {
+        Ok(Self::accrue_interest(self, current_slot)?)
+    }
+    fn cumulative_borrow_rate(&self) -> Result<Option<f64>> {
+        Ok(Some(scaled_decimal_to_f64(
+            self.liquidity.cumulative_borrow_rate_wads.to_scaled_val()?,
+        )))
+    }
+    fn collateral_exchange_rate(&self) -> Result<Option<f64>> {
+        // converted to tulip's exchange rate, which all platforms' rates share a scale with
+        let rate = Rate::from(CollateralExchangeRate::from(
+            Self::collateral_exchange_rate(self)?,
+        ));
+        Ok(Some(rate.to_f64()))
+    }
+    fn protocol_take_rate(&self) -> Result<f64> {
+        Ok(self.config.protocol_take_rate as f64 / 100_f64)
+    }
+}
+
+impl LendingReserveAdapter for port_state::Reserve {
+    fn platform(&self) -> &'static str {
+        "port"
+    }
+    fn available_amount(&self) -> Result<u64> {
+        Ok(self.liquidity.available_amount)
+    }
+    fn borrowed_amount(&self) -> Result<u64> {
+        Ok(self.liquidity.borrowed_amount_wads.try_floor_u64()?)
+    }
+    fn decimals(&self) -> u8 {
+        self.liquidity.mint_decimals
+    }
+    fn utilization_rate(&self) -> Result<f64> {
+        Ok(scaled_rate_to_f64(
+            self.liquidity.utilization_rate()?.to_scaled_val(),
+        ))
+    }
+    fn borrow_rate(&self) -> Result<f64> {
+        Ok(scaled_rate_to_f64(
+            self.current_borrow_rate()?.to_scaled_val(),
+        ))
+    }
+    fn last_update_slot(&self) -> Option<u64> {
+        Some(self.last_update.slot)
+    }
+    fn accrue_interest(&mut self, current_slot: u64) -> Result<()> {
+        Ok(Self::accrue_interest(self, current_slot)?)
+    }
+    fn cumulative_borrow_rate(&self) -> Result<Option<f64>> {
+        Ok(Some(scaled_decimal_to_f64(
+            self.liquidity.cumulative_borrow_rate_wads.to_scaled_val()?,
+        )))
+    }
+    fn collateral_exchange_rate(&self) -> Result<Option<f64>> {
+        // converted to tulip's exchange rate, which all platforms' rates share a scale with
+        let rate = Rate::from(CollateralExchangeRate::from(
+            Self::collateral_exchange_rate(self)?,
+        ));
+        Ok(Some(rate.to_f64()))
+    }
+    /// port only charges borrow and flash loan fees, which are paid on top of the
+    /// borrowed amount, so suppliers earn the full interest
+    fn protocol_take_rate(&self) -> Result<f64> {
+        Ok(0_f64)
+    }
+}
+
+/// the state of a mango token, which is spread across the group, root bank,
+/// node bank and cache accounts rather than held in a single reserve
+#[derive(Clone, Copy)]
+pub struct MangoReserve {
+    pub root_bank: RootBank,
+    /// node bank deposits scaled by the cached deposit index
+    pub total_native_deposit: I80F48,
+    /// node bank borrows scaled by the cached borrow index
+    pub total_native_borrow: I80F48,
+    /// mango doesn't store token decimals, so they are taken from the configuration
+    pub decimals: u8,
+}
+
+impl MangoReserve {
+    fn utilization(&self) -> I80F48 {
+        // taken from RootBank::update_index
+        self.total_native_borrow
+            .checked_div(self.total_native_deposit)
+            .unwrap_or(ZERO_I80F48)
+    }
+    /// returns the (deposit rate, borrow rate) at the current utilization
+    fn rates(&self) -> Result<(I80F48, I80F48)> {
+        match compute_deposit_rate(&self.root_bank, self.utilization()) {
+            Some(rates) => Ok(rates),
+            None => Err(anyhow!("failed to compute deposit and interest rates")),
+        }
+    }
+}
+
+impl LendingReserveAdapter for MangoReserve {
+    fn platform(&self) -> &'static str {
+        "mango"
+    }
+    fn available_amount(&self) -> Result<u64> {
+        let available = match self
+            .total_native_deposit
+            .checked_sub(self.total_native_borrow)
+        {
+            Some(available) => available,
+            None => return Err(anyhow!("failed to compute available amount")),
+        };
+        fixed_to(available, "available amount")
+    }
+    fn borrowed_amount(&self) -> Result<u64> {
+        fixed_to(self.total_native_borrow, "borrowed amount")
+    }
+    fn decimals(&self) -> u8 {
+        self.decimals
+    }
+    fn utilization_rate(&self) -> Result<f64> {
+        fixed_to(self.utilization(), "utilization")
+    }
+    fn borrow_rate(&self) -> Result<f64> {
+        fixed_to(self.rates()?.1, "borrow rate")
+    }
+    fn supply_rate(&self) -> Result<f64> {
+        fixed_to(self.rates()?.0, "deposit rate")
+    }
+    /// mango compounds whenever the root bank's index is updated, which keepers do
+    /// continuously, so compounding is modelled per second
+    fn compounding_periods_per_year(&self) -> u64 {
+        MANGO_SECONDS_PER_YEAR
+    }
+}
+
+/// converts a scaled `Rate` value, as returned by any platform's `Rate::to_scaled_val`
+fn scaled_rate_to_f64(scaled: u128) -> f64 {
+    Rate::from_scaled_val_big(scaled).to_f64()
+}
+
+/// converts a scaled `Decimal` value, as returned by any platform's `Decimal::to_scaled_val`
+fn scaled_decimal_to_f64(scaled: u128) -> f64 {
+    Decimal::from_scaled_val(scaled).to_f64()
+}
+
+fn fixed_to<T>(value: I80F48, name: &str) -> Result<T>
+where
+    I80F48: CheckedCast<T>,
+{
+    match value.checked_cast() {
+        Some(value) => Ok(value),
+        None => Err(anyhow!("failed to cast {}", name)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::port::port_variable_rate_lending_instructions::math::Decimal as PortDecimal;
+    use common::solend::solend_token_lending::math::Decimal as SolendDecimal;
+    use tulip_state::lending_reserve::{
+        Reserve, ReserveCollateral, ReserveConfig, ReserveLiquidity,
+    };
+
+    #[test]
+    fn test_tulip_adapter() {
+        let reserve = Reserve {
+            liquidity: ReserveLiquidity {
+                available_amount: 300,
+                borrowed_amount_wads: Decimal::from(100_u64),
+                mint_decimals: 6,
+                platform_fees: 10,
+                ..Default::default()
+            },
+            config: ReserveConfig {
+                optimal_utilization_rate: 50,
+                degen_utilization_rate: 90,
+                optimal_borrow_rate: 20,
+                degen_borrow_rate: 50,
+                max_borrow_rate: 150,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let adapter: &dyn LendingReserveAdapter = &reserve;
+        assert_eq!(adapter.platform(), "tulip");
+        assert_eq!(adapter.available_amount().unwrap(), 300);
+        assert_eq!(adapter.borrowed_amount().unwrap(), 100);
+        assert_eq!(adapter.decimals(), 6);
+        assert!((adapter.utilization_rate().unwrap() - 0.25).abs() < 1e-12);
+        assert!((adapter.borrow_rate().unwrap() - 0.1).abs() < 1e-12);
+        assert!((adapter.supply_rate().unwrap() - 0.025).abs() < 1e-12);
+        assert!((adapter.protocol_take_rate().unwrap() - 0.1).abs() < 1e-12);
+        assert!((adapter.net_supply_rate().unwrap() - 0.0225).abs() < 1e-12);
+        assert_eq!(
+            adapter.compounding_periods_per_year(),
+            LENDING_SLOTS_PER_YEAR
+        );
+    }
+
+    #[test]
+    fn test_solend_adapter() {
+        let reserve = solend_state::Reserve {
+            liquidity: solend_state::ReserveLiquidity {
+                available_amount: 300,
+                borrowed_amount_wads: SolendDecimal::from(100_u64),
+                mint_decimals: 9,
+                ..Default::default()
+            },
+            config: solend_state::ReserveConfig {
+                optimal_utilization_rate: 50,
+                min_borrow_rate: 0,
+                optimal_borrow_rate: 20,
+                max_borrow_rate: 150,
+                protocol_take_rate: 20,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let adapter: &dyn LendingReserveAdapter = &reserve;
+        assert_eq!(adapter.platform(), "solend");
+        assert_eq!(adapter.available_amount().unwrap(), 300);
+        assert_eq!(adapter.borrowed_amount().unwrap(), 100);
+        assert_eq!(adapter.decimals(), 9);
+        // 25% utilization is half way to the 50% optimal utilization, so the borrow
+        // rate is half of the 20% optimal borrow rate
+        assert!((adapter.utilization_rate().unwrap() - 0.25).abs() < 1e-12);
+        assert!((adapter.borrow_rate().unwrap() - 0.1).abs() < 1e-12);
+        assert!((adapter.supply_rate().unwrap() - 0.025).abs() < 1e-12);
+        assert!((adapter.protocol_take_rate().unwrap() - 0.2).abs() < 1e-12);
+        assert!((adapter.net_supply_rate().unwrap() - 0.02).abs() < 1e-12);
+        assert_eq!(
+            adapter.compounding_periods_per_year(),
+            LENDING_SLOTS_PER_YEAR
+        );
+    }
+
+    #[test]
+    fn test_port_adapter() {
+        let reserve = port_state::Reserve {
+            liquidity: port_state::ReserveLiquidity {
+                available_amount: 600,
+                borrowed_amount_wads: PortDecimal::from(200_u64),
+                mint_decimals: 6,
+                ..Default::default()
+            },
+            config: port_state::ReserveConfig {
+                optimal_utilization_rate: 50,
+                min_borrow_rate: 4,
+                optimal_borrow_rate: 12,
+                max_borrow_rate: 100,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let adapter: &dyn LendingReserveAdapter = &reserve;
+        assert_eq!(adapter.platform(), "port");
+        assert_eq!(adapter.available_amount().unwrap(), 600);
+        assert_eq!(adapter.borrowed_amount().unwrap(), 200);
+        assert_eq!(adapter.decimals(), 6);
+        // half way to the optimal utilization is half way between the 4% min
+        // and 12% optimal borrow rates
+        assert!((adapter.utilization_rate().unwrap() - 0.25).abs() < 1e-12);
+        assert!((adapter.borrow_rate().unwrap() - 0.08).abs() < 1e-12);
+        assert!((adapter.supply_rate().unwrap() - 0.02).abs() < 1e-12);
+        // port has no take rate, so suppliers earn the full supply rate
+        assert_eq!(adapter.protocol_take_rate().unwrap(), 0_f64);
+        assert!((adapter.net_supply_rate().unwrap() - 0.02).abs() < 1e-12);
+        assert_eq!(
+            adapter.compounding_periods_per_year(),
+            LENDING_SLOTS_PER_YEAR
+        );
+    }
+
+    #[test]
+    fn test_mango_adapter() {
+        let mut root_bank: RootBank = bytemuck::Zeroable::zeroed();
+        root_bank.optimal_util = I80F48::from_num(0.5);
+        root_bank.optimal_rate = I80F48::from_num(0.1);
+        root_bank.max_rate = I80F48::from_num(1);
+        let reserve = MangoReserve {
+            root_bank,
+            total_native_deposit: I80F48::from_num(1000),
+            total_native_borrow: I80F48::from_num(250),
+            decimals: 6,
+        };
+        let adapter: &dyn LendingReserveAdapter = &reserve;
+        assert_eq!(adapter.platform(), "mango");
+        assert_eq!(adapter.available_amount().unwrap(), 750);
+        assert_eq!(adapter.borrowed_amount().unwrap(), 250);
+        assert_eq!(adapter.decimals(), 6);
+        // half way to the optimal utilization the borrow rate is half of the 10%
+        // optimal rate, and the deposit rate pays it on the utilized quarter
+        assert!((adapter.utilization_rate().unwrap() - 0.25).abs() < 1e-9);
+        assert!((adapter.borrow_rate().unwrap() - 0.05).abs() < 1e-9);
+        assert!((adapter.supply_rate().unwrap() - 0.0125).abs() < 1e-9);
+        assert!((adapter.net_supply_rate().unwrap() - 0.0125).abs() < 1e-9);
+        assert_eq!(
+            adapter.compounding_periods_per_year(),
+            MANGO_SECONDS_PER_YEAR
+        );
+        // mango has no per slot interest accrual or indexes to report
+        assert_eq!(adapter.last_update_slot(), None);
+        assert_eq!(adapter.cumulative_borrow_rate().unwrap(), None);
+    }
+
+    #[test]
+    fn test_accrue_to_slot() {
+        let mut reserve = Reserve {
+            liquidity: ReserveLiquidity {
+                available_amount: 500,
+                borrowed_amount_wads: Decimal::from(500_u64),
+                cumulative_borrow_rate_wads: Decimal::one(),
+                ..Default::default()
+            },
+            collateral: ReserveCollateral {
+                mint_total_supply: 1000,
+                ..Default::default()
+            },
+            config: ReserveConfig {
+                optimal_utilization_rate: 50,
+                degen_utilization_rate: 90,
+                optimal_borrow_rate: 20,
+                degen_borrow_rate: 50,
+                max_borrow_rate: 150,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        reserve.last_update.slot = 100;
+        let utilization = LendingReserveAdapter::utilization_rate(&reserve).unwrap();
+
+        // nothing is accrued when the reserve is up to date, or ahead of the slot
+        assert_eq!(accrue_to_slot(&mut reserve, 100).unwrap(), (0, false));
+        assert_eq!(accrue_to_slot(&mut reserve, 50).unwrap(), (0, false));
+
+        // a year of slots accrues roughly a year of interest at the 20% borrow rate
+        let (stale_slots, projected) =
+            accrue_to_slot(&mut reserve, 100 + LENDING_SLOTS_PER_YEAR).unwrap();
+        assert_eq!(stale_slots, LENDING_SLOTS_PER_YEAR);
+        assert!(projected);
+        let borrowed = reserve
+            .liquidity
+            .borrowed_amount_wads
+            .try_floor_u64()
+            .unwrap();
+        assert!(borrowed > 600 && borrowed < 615);
+        assert!(LendingReserveAdapter::utilization_rate(&reserve).unwrap() > utilization);
+
+        // the indexes track the accrued interest
+        let cumulative = LendingReserveAdapter::cumulative_borrow_rate(&reserve)
+            .unwrap()
+            .unwrap();
+        assert!((cumulative - 0.2_f64.exp()).abs() < 1e-6);
+        let exchange_rate = LendingReserveAdapter::collateral_exchange_rate(&reserve)
+            .unwrap()
+            .unwrap();
+        let total_supply = 500_f64 + 500_f64 * cumulative;
+        assert!((exchange_rate - 1000_f64 / total_supply).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_apr_to_apy() {
+        assert_eq!(apr_to_apy(0_f64, LENDING_SLOTS_PER_YEAR), 0_f64);
+        // compounding once per year leaves the rate unchanged
+        assert!((apr_to_apy(0.1, 1) - 0.1).abs() < 1e-12);
+        let monthly = (1_f64 + 0.1 / 12_f64).powi(12) - 1_f64;
+        assert!((apr_to_apy(0.1, 12) - monthly).abs() < 1e-12);
+        // frequent compounding approaches continuous compounding
+        let apy = apr_to_apy(0.1, LENDING_SLOTS_PER_YEAR);
+        assert!((apy - (0.1_f64.exp() - 1_f64)).abs() < 1e-8);
+        let apy = apr_to_apy(0.1, MANGO_SECONDS_PER_YEAR);
+        assert!((apy - (0.1_f64.exp() - 1_f64)).abs() < 1e-8);
+    }
+}
@@ -0,0 +1,296 @@
+//! computes how the lending optimizer should split its deposits across lending platforms.
+//! depositing into a platform increases its available liquidity, lowering its utilization
+//! and therefore its supply rate, so the split maximizing the blended supply rate is found
+//! numerically rather than by depositing everything into the highest rate platform
+
+use anyhow::{anyhow, Result};
+use ndarray::{Array, ArrayView1};
+use optimize::{Minimizer, NelderMeadBuilder};
+
+/// allocations smaller than this fraction of the total deposit are dropped
+pub const MIN_ALLOCATION_FRACTION: f64 = 0.0001;
+
+/// a piecewise linear borrow rate curve. rates and utilizations are fractions, so a
+/// borrow rate of 0.1 is 10%. curves without a degen kink use a degen utilization of 0
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RateCurve {
+    pub min_borrow_rate: f64,
+    pub optimal_utilization_rate: f64,
+    pub optimal_borrow_rate: f64,
+    pub degen_utilization_rate: f64,
+    pub degen_borrow_rate: f64,
+    pub max_borrow_rate: f64,
+}
+
+impl RateCurve {
+    /// returns a curve from parameters given as percentages, the format used
+    /// by the `interest_rate_curve` table
+    pub fn from_percentages(
+        min_borrow_rate: f64,
+        max_borrow_rate: f64,
+        optimal_borrow_rate: f64,
+        optimal_utilization_rate: f64,
+        degen_borrow_rate: f64,
+        degen_utilization_rate: f64,
+    ) -> Self {
+        Self {
+            min_borrow_rate: min_borrow_rate / 100_f64,
+            optimal_utilization_rate: optimal_utilization_rate / 100_f64,
+            optimal_borrow_rate: optimal_borrow_rate / 100_f64,
+            degen_utilization_rate: degen_utilization_rate / 100_f64,
+            degen_borrow_rate: degen_borrow_rate / 100_f64,
+            max_borrow_rate: max_borrow_rate / 100_f64,
+        }
+    }
+    /// returns the borrow rate at the given utilization
+    pub fn borrow_rate(&self, utilization: f64) -> f64 {
+        let utilization = utilization.max(0_f64).min(1_f64);
+        if utilization <= self.optimal_utilization_rate {
+            return interpolate(
+                (0_f64, self.min_borrow_rate),
+                (self.optimal_utilization_rate, self.optimal_borrow_rate),
+                utilization,
+            );
+        }
+        if self.degen_utilization_rate > self.optimal_utilization_rate {
+            if utilization <= self.degen_utilization_rate {
+                return interpolate(
+                    (self.optimal_utilization_rate, self.optimal_borrow_rate),
+                    (self.degen_utilization_rate, self.degen_borrow_rate),
+                    utilization,
+                );
+            }
+            return interpolate(
+                (self.degen_utilization_rate, self.degen_borrow_rate),
+                (1_f64, self.max_borrow_rate),
+                utilization,
+            );
+        }
+        interpolate(
+            (self.optimal_utilization_rate, self.optimal_borrow_rate),
+            (1_f64, self.max_borrow_rate),
+            utilization,
+        )
+    }
+}
+
+/// the state of a single lending platform's reserve for the asset being allocated
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LendingMarket {
+    pub platform: String,
+    /// liquidity available to borrow, including `own_deposit`
+    pub available_amount: f64,
+    pub borrowed_amount: f64,
+    /// the amount the lending optimizer currently has deposited into the platform,
+    /// which is withdrawn before the new allocation is deposited
+    pub own_deposit: f64,
+    pub curve: RateCurve,
+    /// the fraction of borrow interest kept by the protocol
+    pub take_rate: f64,
+}
+
+impl LendingMarket {
+    /// returns the supply rate of the platform after depositing `deposit`, net
+    /// of the protocol's take rate
+    pub fn supply_rate(&self, deposit: f64) -> f64 {
+        let available = (self.available_amount - self.own_deposit).max(0_f64) + deposit;
+        let total = available + self.borrowed_amount;
+        if total <= 0_f64 {
+            return 0_f64;
+        }
+        let utilization = self.borrowed_amount / total;
+        self.curve.borrow_rate(utilization) * utilization * (1_f64 - self.take_rate)
+    }
+}
+
+/// the amount to deposit into a single platform
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Allocation {
+    pub platform: String,
+    pub amount: f64,
+    /// the supply rate of the platform once `amount` is deposited
+    pub supply_rate: f64,
+}
+
+/// a split of the total deposit across platforms
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AllocationPlan {
+    /// one allocation per market, in the order the markets were given
+    pub allocations: Vec<Allocation>,
+    /// the supply rate earned by the entire deposit
+    pub blended_rate: f64,
+}
+
+/// finds the split of `total` across `markets` which maximizes the blended supply rate
+pub fn solve_allocation(markets: &[LendingMarket], total: f64) -> Result<AllocationPlan> {
+    if markets.is_empty() {
+        return Err(anyhow!("no markets to allocate across"));
+    }
+    if !total.is_finite() || total <= 0_f64 {
+        return Err(anyhow!("invalid total deposit {}", total));
+    }
+    // the weights are parameterized with a softmax, turning the constrained problem
+    // into an unconstrained one nelder-mead can search
+    let objective = |weights: ArrayView1<f64>| -blended_rate(markets, &softmax(weights), total);
+    let minimizer = NelderMeadBuilder::default()
+        .xtol(1e-8_f64)
+        .ftol(1e-10_f64)
+        .maxiter(20_000)
+        .build()
+        .map_err(|err| anyhow!("failed to build minimizer {:?}", err))?;
+    let start = Array::from_vec(vec![0_f64; markets.len()]);
+    let solved = softmax(minimizer.minimize(&objective, start.view()).view());
+    // the softmax can't reach a weight of exactly 0, so compare against depositing
+    // everything into a single platform, which is optimal when liquidity is deep
+    let mut best = solved;
+    let mut best_rate = blended_rate(markets, &best, total);
+    for idx in 0..markets.len() {
+        let mut weights = vec![0_f64; markets.len()];
+        weights[idx] = 1_f64;
+        let rate = blended_rate(markets, &weights, total);
+        if rate > best_rate {
+            best = weights;
+            best_rate = rate;
+        }
+    }
+    // drop dust allocations, redistributing them proportionally
+    for weight in best.iter_mut() {
+        if *weight < MIN_ALLOCATION_FRACTION {
+            *weight = 0_f64;
+        }
+    }
+    let sum: f64 = best.iter().sum();
+    let allocations = markets
+        .iter()
+        .zip(best.iter())
+        .map(|(market, weight)| {
+            let amount = total * weight / sum;
+            Allocation {
+                platform: market.platform.clone(),
+                amount,
+                supply_rate: market.supply_rate(amount),
+            }
+        })
+        .collect::<Vec<_>>();
+    let blended_rate = allocations
+        .iter()
+        .map(|allocation| allocation.amount * allocation.supply_rate)
+        .sum::<f64>()
+        / total;
+    Ok(AllocationPlan {
+        allocations,
+        blended_rate,
+    })
+}
+
+/// returns the supply rate earned by `total` when split according to `weights`
+fn blended_rate(markets: &[LendingMarket], weights: &[f64], total: f64) -> f64 {
+    markets
+        .iter()
+        .zip(weights.iter())
+        .map(|(market, weight)| {
+            let amount = total * weight;
+            amount * market.supply_rate(amount)
+        })
+        .sum::<f64>()
+        / total
+}
+
+fn softmax(values: ArrayView1<f64>) -> Vec<f64> {
+    let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let exps = values
+        .iter()
+        .map(|value| (value - max).exp())
+        .collect::<Vec<_>>();
+    let sum: f64 = exps.iter().sum();
+    exps.into_iter().map(|exp| exp / sum).collect()
+}
+
+fn interpolate(from: (f64, f64), to: (f64, f64), x: f64) -> f64 {
+    if to.0 <= from.0 {
+        return to.1;
+    }
+    from.1 + (to.1 - from.1) * (x - from.0) / (to.0 - from.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn curve() -> RateCurve {
+        RateCurve::from_percentages(0_f64, 150_f64, 10_f64, 80_f64, 50_f64, 90_f64)
+    }
+
+    fn market(platform: &str, available_amount: f64, borrowed_amount: f64) -> LendingMarket {
+        LendingMarket {
+            platform: platform.to_string(),
+            available_amount,
+            borrowed_amount,
+            own_deposit: 0_f64,
+            curve: curve(),
+            take_rate: 0_f64,
+        }
+    }
+
+    #[test]
+    fn test_borrow_rate() {
+        let curve = curve();
+        assert!((curve.borrow_rate(0_f64) - 0_f64).abs() < 1e-12);
+        assert!((curve.borrow_rate(0.4) - 0.05).abs() < 1e-12);
+        assert!((curve.borrow_rate(0.8) - 0.1).abs() < 1e-12);
+        assert!((curve.borrow_rate(0.85) - 0.3).abs() < 1e-12);
+        assert!((curve.borrow_rate(0.95) - 1.0).abs() < 1e-12);
+        assert!((curve.borrow_rate(1_f64) - 1.5).abs() < 1e-12);
+        // two slope curves go straight from the optimal point to the max rate
+        let curve = RateCurve::from_percentages(0_f64, 100_f64, 20_f64, 50_f64, 0_f64, 0_f64);
+        assert!((curve.borrow_rate(0.75) - 0.6).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_solve_allocation() {
+        // a single market receives everything
+        let plan = solve_allocation(&[market("tulip", 100_f64, 100_f64)], 50_f64).unwrap();
+        assert_eq!(plan.allocations.len(), 1);
+        assert!((plan.allocations[0].amount - 50_f64).abs() < 1e-9);
+
+        // identical markets are split evenly
+        let markets = vec![
+            market("tulip", 100_f64, 800_f64),
+            market("solend", 100_f64, 800_f64),
+        ];
+        let plan = solve_allocation(&markets, 200_f64).unwrap();
+        assert!((plan.allocations[0].amount - 100_f64).abs() < 1_f64);
+        assert!((plan.allocations[1].amount - 100_f64).abs() < 1_f64);
+
+        // the split beats depositing everything into either market
+        let markets = vec![
+            market("tulip", 50_f64, 450_f64),
+            market("solend", 10_000_f64, 10_000_f64),
+            market("port", 1_000_f64, 4_000_f64),
+        ];
+        let total = 1_000_f64;
+        let plan = solve_allocation(&markets, total).unwrap();
+        let allocated: f64 = plan.allocations.iter().map(|a| a.amount).sum();
+        assert!((allocated - total).abs() < 1e-6);
+        for idx in 0..markets.len() {
+            let mut weights = vec![0_f64; markets.len()];
+            weights[idx] = 1_f64;
+            assert!(plan.blended_rate >= blended_rate(&markets, &weights, total) - 1e-9);
+        }
+        // the highly utilized market is worth depositing into, but not with everything
+        assert!(plan.allocations[0].amount > 0_f64);
+        assert!(plan.allocations[0].amount < total);
+
+        // a protocol keeping most of the interest is avoided
+        let mut markets = vec![
+            market("tulip", 1_000_f64, 4_000_f64),
+            market("solend", 1_000_f64, 4_000_f64),
+        ];
+        markets[1].take_rate = 0.9;
+        let plan = solve_allocation(&markets, total).unwrap();
+        assert!(plan.allocations[0].amount > plan.allocations[1].amount);
+
+        assert!(solve_allocation(&[], total).is_err());
+        assert!(solve_allocation(&markets, 0_f64).is_err());
+    }
+}
@@ -2,26 +2,31 @@
 
 #![deny(unused_must_use)]
 
-use anchor_client::{solana_client::rpc_client::RpcClient, solana_sdk::program_pack::Pack};
+use anchor_client::solana_sdk::account::Account;
+use anchor_client::solana_sdk::program_pack::Pack;
+use anchor_client::solana_sdk::pubkey::Pubkey;
 use anyhow::{anyhow, Result};
-use az::CheckedCast;
+use log::{debug, warn};
 
 use common::mango::mango_common::Loadable;
-use common::mango::mango_lib::state::ZERO_I80F48;
 use common::mango::mango_lib::state::{MangoCache, MangoGroup, NodeBank, RootBank};
-use common::mango::mango_lib::utils::compute_deposit_rate;
-use common::math::decimal::Decimal;
+use common::port::port_variable_rate_lending_instructions::state as port_state;
 use common::solend::solend_token_lending::state as solend_state;
 use common::tulip as tulip_state;
-use common::{
-    math::rate::Rate, port::port_variable_rate_lending_instructions::state as port_state,
+use common::tulip::lending_last_update::STALE_AFTER_SLOTS_ELAPSED;
+
+use crate::rate_lookup::account_source::AccountSource;
+use crate::rate_lookup::adapter::{
+    accrue_to_slot, apr_to_apy, LendingReserveAdapter, MangoReserve,
 };
+use crate::rate_lookup::metrics;
 
+use config::analytics::interest_rates::AssetRate;
 use config::analytics::Platform;
 use config::Configuration;
 
+use std::collections::HashMap;
 use std::convert::TryInto;
-use std::str::FromStr;
 use std::sync::Arc;
 #[derive(Default, Debug, Clone)]
 pub struct InterestRateSample {
@@ -32,298 +37,465 @@ pub struct InterestRateSample {
     pub borrowed_amount: f64,
     pub utilization_rate: f64, // utilization rate
     pub interest_rate: f64,    // lending rate
+    /// the lending rate after protocol take rates
+    pub net_interest_rate: f64,
+    /// `rate`, `interest_rate` and `net_interest_rate` compounded according
+    /// to the platform's compounding model
+    pub borrow_apy: f64,
+    pub interest_apy: f64,
+    pub net_interest_apy: f64,
+    /// the reserve's cumulative borrow rate index, 0 for platforms without one
+    pub cumulative_borrow_rate: f64,
+    /// collateral tokens per unit of liquidity, 0 for platforms without collateral tokens
+    pub collateral_exchange_rate: f64,
+    /// the slot the account data was observed at, 0 if unknown
+    pub slot: u64,
+    /// the number of slots the reserve had gone without accruing interest
+    pub stale_slots: u64,
+    /// true when interest was accrued up to `slot`, projecting the values the
+    /// reserve would report if refreshed, rather than the values stored on chain
+    pub projected: bool,
+}
+
+/// the stage at which an interest rate lookup failed, allowing callers to tell
+/// rpc failures apart from account data which couldn't be decoded, or used
+#[derive(Debug, thiserror::Error)]
+pub enum LookupError {
+    /// the asset isn't configured, or its account keys couldn't be derived
+    #[error(transparent)]
+    Config(anyhow::Error),
+    /// the accounts couldn't be fetched from the account source
+    #[error(transparent)]
+    Fetch(anyhow::Error),
+    /// account data was missing, or couldn't be deserialized
+    #[error(transparent)]
+    Decode(anyhow::Error),
+    /// account data was decoded, but rates couldn't be computed from it
+    #[error(transparent)]
+    Compute(anyhow::Error),
+}
+
+/// lookup interest rates for the given asset and platform, values returned are in APR.
+/// accounts are fetched asynchronously from the given account source, allowing lookups
+/// to be driven by the tokio runtime without tying up a worker thread for the duration
+/// of the rpc request, or to be replayed from a recorded snapshot
+pub async fn interest_rate_nonblocking(
+    config: &Arc<Configuration>,
+    source: &dyn AccountSource,
+    asset: &str,
+    platform: &str,
+) -> Result<InterestRateSample, LookupError> {
+    observe_lookup_result(
+        asset,
+        platform,
+        __interest_rate_nonblocking(config, source, asset, platform).await,
+    )
+}
+
+async fn __interest_rate_nonblocking(
+    config: &Arc<Configuration>,
+    source: &dyn AccountSource,
+    asset: &str,
+    platform: &str,
+) -> Result<InterestRateSample, LookupError> {
+    let rate_config = rate_config(config, asset, platform).map_err(LookupError::Config)?;
+
+    let account_keys = rate_config.account_keys().map_err(LookupError::Config)?;
+
+    let (slot, accounts) = source
+        .get_multiple_accounts(&account_keys)
+        .await
+        .map_err(LookupError::Fetch)?;
+
+    interest_rate_from_accounts(&rate_config, &to_account_map(&account_keys, accounts), slot)
+}
+
+/// an interest rate sample which was cross checked against the
+/// same accounts, read from multiple independent backends
+#[derive(Default, Debug, Clone)]
+pub struct QuorumSample {
+    /// the sample computed from the first, and healthiest backend
+    pub sample: InterestRateSample,
+    /// the number of backends which returned the accounts
+    pub responses: usize,
+    /// the largest absolute difference in utilization rate between the
+    /// primary sample, and the samples computed from any other backend
+    pub max_utilization_diff: f64,
 }
 
-/// lookup interest rates for the given asset and platform
-/// values returns are in APR
-pub fn interest_rate(
+/// same as `interest_rate_nonblocking` except that the accounts are read from up to
+/// `quorum` backends, computing a sample from each. callers should compare
+/// `max_utilization_diff` against a threshold to detect a lagging or faulty backend
+pub async fn interest_rate_quorum(
     config: &Arc<Configuration>,
-    rpc: &Arc<RpcClient>,
+    source: &dyn AccountSource,
     asset: &str,
     platform: &str,
+    quorum: usize,
+) -> Result<QuorumSample, LookupError> {
+    observe_lookup_result(
+        asset,
+        platform,
+        __interest_rate_quorum(config, source, asset, platform, quorum).await,
+    )
+}
+
+async fn __interest_rate_quorum(
+    config: &Arc<Configuration>,
+    source: &dyn AccountSource,
+    asset: &str,
+    platform: &str,
+    quorum: usize,
+) -> Result<QuorumSample, LookupError> {
+    let rate_config = rate_config(config, asset, platform).map_err(LookupError::Config)?;
+
+    let account_keys = rate_config.account_keys().map_err(LookupError::Config)?;
+
+    let responses = source
+        .get_multiple_accounts_quorum(&account_keys, quorum)
+        .await
+        .map_err(LookupError::Fetch)?;
+    let mut samples = Vec::with_capacity(responses.len());
+    for (slot, accounts) in responses.into_iter() {
+        samples.push(interest_rate_from_accounts(
+            &rate_config,
+            &to_account_map(&account_keys, accounts),
+            slot,
+        )?);
+    }
+    if samples.is_empty() {
+        return Err(LookupError::Fetch(anyhow!("no quorum responses")));
+    }
+    let primary = samples.remove(0);
+    let max_utilization_diff = samples
+        .iter()
+        .map(|sample| (sample.utilization_rate - primary.utilization_rate).abs())
+        .fold(0_f64, f64::max);
+    Ok(QuorumSample {
+        responses: samples.len() + 1,
+        sample: primary,
+        max_utilization_diff,
+    })
+}
+
+/// maps account key -> account data, skipping accounts which do not exist
+fn to_account_map(
+    account_keys: &[Pubkey],
+    accounts: Vec<Option<Account>>,
+) -> HashMap<Pubkey, Account> {
+    account_keys
+        .iter()
+        .zip(accounts)
+        .filter_map(|(key, account)| Some((*key, account?)))
+        .collect()
+}
+
+/// records the outcome of a lookup in the `RATE_LOOKUPS` metric, returning the result unchanged
+fn observe_lookup_result<T, E>(asset: &str, platform: &str, result: Result<T, E>) -> Result<T, E> {
+    let outcome = if result.is_ok() {
+        metrics::OUTCOME_SUCCESS
+    } else {
+        metrics::OUTCOME_ERROR
+    };
+    metrics::observe_lookup(platform, asset, outcome);
+    result
+}
+
+/// returns the interest rate configuration for the given platform and asset
+fn rate_config(config: &Arc<Configuration>, asset: &str, platform: &str) -> Result<AssetRate> {
+    match config.analytics.interest_rates.rate(platform, asset) {
+        Ok(config) => Ok(config),
+        Err(err) => Err(anyhow!(
+            "failed to find interest_rate for platform({})-asset({}) {:?}",
+            platform,
+            asset,
+            err
+        )),
+    }
+}
+
+/// computes an interest rate sample for the asset described by `rate_config`, using
+/// previously fetched account data. `account_map` must contain every account
+/// returned by `rate_config.account_keys()`.
+///
+/// reserves are projected to `current_slot` by accruing interest on a copy of the
+/// reserve, so that reserves which haven't been refreshed recently don't report
+/// outdated borrowed amounts and utilization. a `current_slot` of 0 disables projection
+pub fn interest_rate_from_accounts(
+    rate_config: &AssetRate,
+    account_map: &HashMap<Pubkey, Account>,
+    current_slot: u64,
+) -> Result<InterestRateSample, LookupError> {
+    let reserve = reserve_adapter(rate_config, account_map).map_err(LookupError::Decode)?;
+    sample_reserve(rate_config, reserve, current_slot).map_err(LookupError::Compute)
+}
+
+/// computes an interest rate sample from a decoded reserve
+fn sample_reserve(
+    rate_config: &AssetRate,
+    mut reserve: Box<dyn LendingReserveAdapter>,
+    current_slot: u64,
 ) -> Result<InterestRateSample> {
-    // retrieve the config that describes all the routes needed to compute price
-    let rate_config = match config.analytics.interest_rates.rate(platform, asset) {
-        Ok(config) => config,
-        Err(err) => {
-            return Err(anyhow!(
-                "failed to find interest_rate for platform({})-asset({}) {:?}",
-                platform,
-                asset,
-                err
-            ))
-        }
+    let platform = reserve.platform();
+    let (stale_slots, projected) = if current_slot > 0 {
+        accrue_to_slot(reserve.as_mut(), current_slot)?
+    } else {
+        (0, false)
     };
+    if stale_slots >= STALE_AFTER_SLOTS_ELAPSED {
+        debug!(
+            "{} reserve for {} was stale for {} slots",
+            platform, rate_config.asset, stale_slots
+        );
+    }
+    let available_amount = reserve.available_amount()?;
+    let borrowed_amount = reserve.borrowed_amount()?;
+    ensure_i64_to_u64_safety(
+        available_amount,
+        &format!("available_amount ({})", platform),
+    )?;
+    ensure_i64_to_u64_safety(borrowed_amount, &format!("borrowed_amount ({})", platform))?;
+    let borrow_rate = reserve.borrow_rate()?;
+    let supply_rate = reserve.supply_rate()?;
+    let net_supply_rate = reserve.net_supply_rate()?;
+    let periods = reserve.compounding_periods_per_year();
+    // rates are stored as percentages
+    Ok(InterestRateSample {
+        asset: rate_config.asset.clone(),
+        platform: rate_config.platform.to_string(),
+        rate: borrow_rate * 100_f64,
+        utilization_rate: reserve.utilization_rate()? * 100_f64,
+        available_amount: spl_token::amount_to_ui_amount(available_amount, reserve.decimals()),
+        borrowed_amount: spl_token::amount_to_ui_amount(borrowed_amount, reserve.decimals()),
+        interest_rate: supply_rate * 100_f64,
+        net_interest_rate: net_supply_rate * 100_f64,
+        borrow_apy: apr_to_apy(borrow_rate, periods) * 100_f64,
+        interest_apy: apr_to_apy(supply_rate, periods) * 100_f64,
+        net_interest_apy: apr_to_apy(net_supply_rate, periods) * 100_f64,
+        cumulative_borrow_rate: reserve.cumulative_borrow_rate()?.unwrap_or_default(),
+        collateral_exchange_rate: reserve.collateral_exchange_rate()?.unwrap_or_default(),
+        slot: current_slot,
+        stale_slots,
+        projected,
+    })
+}
 
-    // fetch a deduped list of accounts needed
-    let account_keys = rate_config.account_keys()?;
-
-    // create a hashmap which maps account key -> account data
-    let account_map = crate::account_keys_to_account_map(&account_keys, rpc)?;
-
-    let (borrow_rate, interest_rate, utilization_rate, available_amount, borrowed_amount) =
-        if let Some(spl_config) = rate_config.spl_lending_config {
-            if let Some(reserve) = account_map.get(&spl_config.reserve()) {
-                match rate_config.platform {
-                    Platform::MangoV3 => return Err(anyhow!("mangov3 unsupported")),
-                    Platform::Port => {
-                        let reserve_account =
-                            port_state::Reserve::unpack_unchecked(&reserve.data[..])?;
-
-                        let available_amount = reserve_account.liquidity.available_amount;
-                        let borrowed_amount = reserve_account
-                            .liquidity
-                            .borrowed_amount_wads
-                            .try_floor_u64()?;
-                        // convert to a common format to ensure consistency across all metrics
-                        // we're using the tulip lending program as the standard :based_cigar:
-                        let port_rate = reserve_account.current_borrow_rate()?;
-                        let port_util_rate = reserve_account.liquidity.utilization_rate()?;
-
-                        let port_rate_u128 = port_rate.to_scaled_val();
-                        let port_util_rate_u128 = port_util_rate.to_scaled_val();
-
-                        let tulip_standard_borrow_rate =
-                            Decimal::from(Rate::from_scaled_val_big(port_rate_u128)).to_string();
-                        let tulip_standard_util_rate =
-                            Decimal::from(Rate::from_scaled_val_big(port_util_rate_u128))
-                                .to_string();
-
-                        ensure_i64_to_u64_safety(available_amount, "available_amount (port)")?;
-                        ensure_i64_to_u64_safety(borrowed_amount, "borrowed_amount (port)")?;
-
-                        let borrow_rate =
-                            f64::from_str(tulip_standard_borrow_rate.as_str()).unwrap();
-                        let utilization_rate =
-                            f64::from_str(tulip_standard_util_rate.as_str()).unwrap();
-                        let interest_rate = borrow_rate * utilization_rate;
-
-                        let available_amount = spl_token::amount_to_ui_amount(
-                            available_amount,
-                            reserve_account.liquidity.mint_decimals,
-                        );
-                        let borrowed_amount = spl_token::amount_to_ui_amount(
-                            borrowed_amount,
-                            reserve_account.liquidity.mint_decimals,
-                        );
-                        (
-                            borrow_rate * 100_f64,
-                            interest_rate * 100_f64,
-                            utilization_rate * 100_f64,
-                            available_amount,
-                            borrowed_amount,
-                        )
-                    }
-                    Platform::Tulip => {
-                        let reserve_account =
-                            tulip_state::lending_reserve::Reserve::unpack_unchecked(
-                                &reserve.data[..],
-                            )?;
-                        let available_amount = reserve_account.liquidity.available_amount;
-                        let borrowed_amount = reserve_account
-                            .liquidity
-                            .borrowed_amount_wads
-                            .try_floor_u64()?;
-
-                        // convert to a common format to ensure consistency across all metrics
-                        // we're using the tulip lending program as the standard :based_cigar:
-                        let tulip_rate = reserve_account.current_borrow_rate()?;
-                        let tulip_util_rate = reserve_account.liquidity.utilization_rate()?;
-                        let tulip_rate_u128 = tulip_rate.to_scaled_val();
-                        let tulip_util_rate_u128 = tulip_util_rate.to_scaled_val();
-
-                        let tulip_standard_borrow_rate =
-                            Decimal::from(Rate::from_scaled_val_big(tulip_rate_u128)).to_string();
-                        let tulip_standard_util_rate =
-                            Decimal::from(Rate::from_scaled_val_big(tulip_util_rate_u128))
-                                .to_string();
-
-                        ensure_i64_to_u64_safety(available_amount, "available_amount (port)")?;
-                        ensure_i64_to_u64_safety(borrowed_amount, "borrowed_amount (port)")?;
-
-                        let borrow_rate =
-                            f64::from_str(tulip_standard_borrow_rate.as_str()).unwrap();
-                        let utilization_rate =
-                            f64::from_str(tulip_standard_util_rate.as_str()).unwrap();
-                        let interest_rate = borrow_rate * utilization_rate;
-                        let available_amount = spl_token::amount_to_ui_amount(
-                            available_amount,
-                            reserve_account.liquidity.mint_decimals,
-                        );
-                        let borrowed_amount = spl_token::amount_to_ui_amount(
-                            borrowed_amount,
-                            reserve_account.liquidity.mint_decimals,
-                        );
-                        (
-                            borrow_rate * 100_f64,
-                            interest_rate * 100_f64,
-                            utilization_rate * 100_f64,
-                            available_amount,
-                            borrowed_amount,
-                        )
-                    }
-                    Platform::Solend => {
-                        let reserve_account =
-                            solend_state::Reserve::unpack_unchecked(&reserve.data[..])?;
-                        let available_amount = reserve_account.liquidity.available_amount;
-                        let borrowed_amount = reserve_account
-                            .liquidity
-                            .borrowed_amount_wads
-                            .try_floor_u64()?;
-                        // convert to a common format to ensure consistency across all metrics
-                        // we're using the tulip lending program as the standard :based_cigar:
-                        let solend_rate = reserve_account.current_borrow_rate()?;
-                        let solend_util_rate = reserve_account.liquidity.utilization_rate()?;
-
-                        let solend_rate_u128 = solend_rate.to_scaled_val();
-                        let solend_util_rate_u128 = solend_util_rate.to_scaled_val();
-
-                        let tulip_standard_borrow_rate =
-                            Decimal::from(Rate::from_scaled_val_big(solend_rate_u128)).to_string();
-                        let tulip_standard_util_rate =
-                            Decimal::from(Rate::from_scaled_val_big(solend_util_rate_u128))
-                                .to_string();
-
-                        ensure_i64_to_u64_safety(available_amount, "available_amount (port)")?;
-                        ensure_i64_to_u64_safety(borrowed_amount, "borrowed_amount (port)")?;
-
-                        let borrow_rate =
-                            f64::from_str(tulip_standard_borrow_rate.as_str()).unwrap();
-                        let utilization_rate =
-                            f64::from_str(tulip_standard_util_rate.as_str()).unwrap();
-                        let interest_rate = borrow_rate * utilization_rate;
-                        let available_amount = spl_token::amount_to_ui_amount(
-                            available_amount,
-                            reserve_account.liquidity.mint_decimals,
-                        );
-                        let borrowed_amount = spl_token::amount_to_ui_amount(
-                            borrowed_amount,
-                            reserve_account.liquidity.mint_decimals,
-                        );
-                        (
-                            borrow_rate * 100_f64,
-                            interest_rate * 100_f64,
-                            utilization_rate * 100_f64,
-                            available_amount,
-                            borrowed_amount,
-                        )
-                    }
-                    _ => return Err(anyhow!("invalid platform for interest rate sampling")),
-                }
-            } else {
+/// decodes the reserve described by `rate_config` from previously fetched account data
+pub fn reserve_adapter(
+    rate_config: &AssetRate,
+    account_map: &HashMap<Pubkey, Account>,
+) -> Result<Box<dyn LendingReserveAdapter>> {
+    if let Some(spl_config) = &rate_config.spl_lending_config {
+        let reserve = match account_map.get(&spl_config.reserve()) {
+            Some(reserve) => reserve,
+            None => {
                 return Err(anyhow!(
                     "failed to fine reserve account for {}",
                     spl_config.reserve()
-                ));
+                ))
             }
-        } else if let Some(mango_config) = rate_config.mango_config {
-            let group_account = match account_map.get(&mango_config.group()) {
-                Some(account) => account,
-                None => return Err(anyhow!("mango group account not found")),
-            };
-            let cache_account = match account_map.get(&mango_config.cache()) {
-                Some(account) => account,
-                None => return Err(anyhow!("mango cache account not found")),
-            };
-            let root_bank_account = match account_map.get(&mango_config.root_bank()) {
-                Some(account) => account,
-                None => return Err(anyhow!("mango root_bank account not found")),
-            };
-            let node_bank_account = match account_map.get(&mango_config.node_bank()) {
-                Some(account) => account,
-                None => return Err(anyhow!("mango group account not found")),
-            };
-            let _group_token_account_account =
-                match account_map.get(&mango_config.group_token_account()) {
-                    Some(account) => account,
-                    None => return Err(anyhow!("mango group account not found")),
-                };
-
-            let mango_group = MangoGroup::load_from_bytes(&group_account.data[..])?;
-            let mango_root_bank = RootBank::load_from_bytes(&root_bank_account.data[..])?;
-            let mango_node_bank = NodeBank::load_from_bytes(&node_bank_account.data[..])?;
-            let mango_cache = MangoCache::load_from_bytes(&cache_account.data[..])?;
-
-            let token_index = match mango_group.find_root_bank_index(&mango_config.root_bank()) {
-                Some(idx) => idx,
-                None => {
-                    return Err(anyhow!(
-                        "failed to find root bank {} in group {}",
-                        mango_config.root_bank(),
-                        mango_config.group()
-                    ))
-                }
-            };
+        };
+        match rate_config.platform {
+            Platform::MangoV3 => Err(anyhow!("mangov3 unsupported")),
+            Platform::Port => Ok(Box::new(port_state::Reserve::unpack_unchecked(
+                &reserve.data[..],
+            )?)),
+            Platform::Tulip => Ok(Box::new(
+                tulip_state::lending_reserve::Reserve::unpack_unchecked(&reserve.data[..])?,
+            )),
+            Platform::Solend => Ok(Box::new(solend_state::Reserve::unpack_unchecked(
+                &reserve.data[..],
+            )?)),
+            _ => Err(anyhow!("invalid platform for interest rate sampling")),
+        }
+    } else if let Some(mango_config) = &rate_config.mango_config {
+        let group_account = match account_map.get(&mango_config.group()) {
+            Some(account) => account,
+            None => return Err(anyhow!("mango group account not found")),
+        };
+        let cache_account = match account_map.get(&mango_config.cache()) {
+            Some(account) => account,
+            None => return Err(anyhow!("mango cache account not found")),
+        };
+        let root_bank_account = match account_map.get(&mango_config.root_bank()) {
+            Some(account) => account,
+            None => return Err(anyhow!("mango root_bank account not found")),
+        };
+        let node_bank_account = match account_map.get(&mango_config.node_bank()) {
+            Some(account) => account,
+            None => return Err(anyhow!("mango node_bank account not found")),
+        };
+        if account_map
+            .get(&mango_config.group_token_account())
+            .is_none()
+        {
+            return Err(anyhow!("mango group token account not found"));
+        }
 
-            let root_bank_cache = &mango_cache.root_bank_cache[token_index];
-            let total_native_deposit = mango_node_bank.deposits * root_bank_cache.deposit_index;
-            let total_native_borrow = mango_node_bank.borrows * root_bank_cache.borrow_index;
-            // taken from RootBank::update_index
-            let utilization = total_native_borrow
-                .checked_div(total_native_deposit)
-                .unwrap_or(ZERO_I80F48);
-            // calculate interest rate
-
-            let (deposit_interest, interest_rate) =
-                match compute_deposit_rate(mango_root_bank, utilization) {
-                    Some((deposit_interest, interest_rate)) => (deposit_interest, interest_rate),
-                    None => return Err(anyhow!("failed to compute deposit and interest rates")),
-                };
-
-            let borrow_interest: f64 = match interest_rate.checked_cast() {
-                Some(val) => val,
-                None => return Err(anyhow!("failed to cast borrow rate to float")),
-            };
-            let deposit_interest: f64 = match deposit_interest.checked_cast() {
-                Some(val) => val,
-                None => return Err(anyhow!("failed to cast interest rate to float64")),
-            };
-            let utilization: f64 = match utilization.checked_cast() {
-                Some(val) => val,
-                None => return Err(anyhow!("failed to cast utilization to float64")),
-            };
-            let borrow_interest = borrow_interest * 100_f64;
-            let deposit_interest = deposit_interest * 100_f64;
-            let utilization = utilization * 100_f64;
+        let mango_group = MangoGroup::load_from_bytes(&group_account.data[..])?;
+        let mango_root_bank = RootBank::load_from_bytes(&root_bank_account.data[..])?;
+        let mango_node_bank = NodeBank::load_from_bytes(&node_bank_account.data[..])?;
+        let mango_cache = MangoCache::load_from_bytes(&cache_account.data[..])?;
 
-            let available_amount = total_native_deposit
-                .checked_sub(total_native_borrow)
-                .unwrap();
+        let token_index = match mango_group.find_root_bank_index(&mango_config.root_bank()) {
+            Some(idx) => idx,
+            None => {
+                return Err(anyhow!(
+                    "failed to find root bank {} in group {}",
+                    mango_config.root_bank(),
+                    mango_config.group()
+                ))
+            }
+        };
 
-            let available_amount: u64 = match available_amount.checked_cast() {
-                Some(val) => val,
-                None => return Err(anyhow!("failed to cast avaialble amount to f64")),
-            };
+        let root_bank_cache = &mango_cache.root_bank_cache[token_index];
+        Ok(Box::new(MangoReserve {
+            root_bank: *mango_root_bank,
+            total_native_deposit: mango_node_bank.deposits * root_bank_cache.deposit_index,
+            total_native_borrow: mango_node_bank.borrows * root_bank_cache.borrow_index,
+            decimals: rate_config.decimals,
+        }))
+    } else {
+        Err(anyhow!("no valid config to derive rates from"))
+    }
+}
 
-            let borrowed_amount: u64 = match total_native_borrow.checked_cast() {
-                Some(val) => val,
-                None => return Err(anyhow!("failed to cast borrowed amount to f64")),
-            };
+/// the maximum number of accounts which can be requested with a single getMultipleAccounts call
+pub const MAX_MULTIPLE_ACCOUNTS: usize = 100;
 
-            let decimals = rate_config.decimals;
+/// the number of times chunks which were returned at an older slot are refetched
+/// when attempting to pin a batched account fetch to a single slot
+pub const MAX_SLOT_PIN_ATTEMPTS: usize = 3;
 
-            let available_amount = spl_token::amount_to_ui_amount(available_amount, decimals);
-            let borrowed_amount = spl_token::amount_to_ui_amount(borrowed_amount, decimals);
+/// account data for a set of accounts, fetched at a single slot
+#[derive(Default, Debug, Clone)]
+pub struct AccountSnapshot {
+    /// the context slot the accounts were returned at
+    pub slot: u64,
+    /// maps account key -> account data
+    pub accounts: HashMap<Pubkey, Account>,
+}
 
+/// looks up the interest rates for every asset in `config.analytics.interest_rates.assets`
+/// using a single batched account fetch. the account keys required by every asset are
+/// gathered and deduped, fetched in chunks of `MAX_MULTIPLE_ACCOUNTS`, and every sample
+/// is computed from the same account snapshot.
+///
+/// the returned vector contains one entry for each configured asset, in the order they
+/// are configured, so that a failure to compute one sample, or an asset whose account
+/// keys can't be derived from its configuration, doesn't fail the others
+pub async fn interest_rates_batched(
+    config: &Arc<Configuration>,
+    source: &dyn AccountSource,
+) -> Result<(
+    u64,
+    Vec<(AssetRate, Result<InterestRateSample, LookupError>)>,
+)> {
+    let assets = &config.analytics.interest_rates.assets;
+    let mut account_keys: Vec<Pubkey> = Vec::with_capacity(assets.len() * 5);
+    let mut key_errors: HashMap<usize, LookupError> = HashMap::new();
+    for (idx, asset) in assets.iter().enumerate() {
+        match asset.account_keys() {
+            Ok(keys) => {
+                for key in keys {
+                    if !account_keys.contains(&key) {
+                        account_keys.push(key);
+                    }
+                }
+            }
+            Err(err) => {
+                key_errors.insert(
+                    idx,
+                    LookupError::Config(anyhow!(
+                        "failed to derive account keys for platform({})-asset({}) {:?}",
+                        asset.platform,
+                        asset.asset,
+                        err
+                    )),
+                );
+            }
+        }
+    }
+    let snapshot = match fetch_account_snapshot(source, &account_keys).await {
+        Ok(snapshot) => snapshot,
+        Err(err) => {
+            for asset in assets.iter() {
+                metrics::observe_lookup(
+                    &asset.platform.to_string(),
+                    &asset.asset,
+                    metrics::OUTCOME_ERROR,
+                );
+            }
+            return Err(err);
+        }
+    };
+    let samples = assets
+        .iter()
+        .enumerate()
+        .map(|(idx, asset)| {
+            let result = match key_errors.remove(&idx) {
+                Some(err) => Err(err),
+                None => interest_rate_from_accounts(asset, &snapshot.accounts, snapshot.slot),
+            };
             (
-                borrow_interest,
-                deposit_interest,
-                utilization,
-                available_amount,
-                borrowed_amount,
+                asset.clone(),
+                observe_lookup_result(&asset.asset, &asset.platform.to_string(), result),
             )
-        } else {
-            return Err(anyhow!("no valid config to derive rates from"));
-        };
+        })
+        .collect();
+    Ok((snapshot.slot, samples))
+}
 
-    Ok(InterestRateSample {
-        asset: asset.to_string(),
-        platform: platform.to_string(),
-        rate: borrow_rate,
-        utilization_rate,
-        available_amount,
-        borrowed_amount,
-        interest_rate,
+/// fetches the given accounts using chunked getMultipleAccounts requests, attempting
+/// to pin all chunks to the same slot. chunks returned at a slot older than the most
+/// recent chunk are refetched up to `MAX_SLOT_PIN_ATTEMPTS` times, after which the
+/// oldest slot observed is reported as the snapshot slot
+pub async fn fetch_account_snapshot(
+    source: &dyn AccountSource,
+    account_keys: &[Pubkey],
+) -> Result<AccountSnapshot> {
+    let chunks: Vec<&[Pubkey]> = account_keys.chunks(MAX_MULTIPLE_ACCOUNTS).collect();
+    let mut chunk_slots = vec![0_u64; chunks.len()];
+    let mut accounts = HashMap::with_capacity(account_keys.len());
+    for attempt in 0..=MAX_SLOT_PIN_ATTEMPTS {
+        let max_slot = chunk_slots.iter().copied().max().unwrap_or_default();
+        for (idx, chunk) in chunks.iter().enumerate() {
+            // on the first attempt every chunk is fetched, afterwards only the
+            // chunks which are behind the most recently observed slot are refetched
+            if attempt > 0 && chunk_slots[idx] == max_slot {
+                continue;
+            }
+            let (slot, chunk_accounts) = source.get_multiple_accounts(chunk).await?;
+            chunk_slots[idx] = slot;
+            for (key, account) in chunk.iter().zip(chunk_accounts) {
+                match account {
+                    Some(account) => {
+                        accounts.insert(*key, account);
+                    }
+                    None => {
+                        accounts.remove(key);
+                    }
+                }
+            }
+        }
+        let min_slot = chunk_slots.iter().copied().min().unwrap_or_default();
+        let max_slot = chunk_slots.iter().copied().max().unwrap_or_default();
+        if min_slot == max_slot {
+            return Ok(AccountSnapshot {
+                slot: max_slot,
+                accounts,
+            });
+        }
+    }
+    let min_slot = chunk_slots.iter().copied().min().unwrap_or_default();
+    warn!(
+        "failed to pin {} account chunks to a single slot, using oldest slot {}",
+        chunks.len(),
+        min_slot
+    );
+    Ok(AccountSnapshot {
+        slot: min_slot,
+        accounts,
     })
 }
 
@@ -351,77 +523,154 @@ pub fn ensure_i64_to_u64_safety(value: u64, name: &str) -> Result<()> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use anchor_client::solana_client::rpc_client::RpcClient;
-
-    use config::analytics::interest_rates::AssetRate;
-    use config::analytics::interest_rates::{MangoConfiguration, SplLendingConfiguration};
-
-    use config::analytics::Platform;
-
-    use config::Configuration;
-
-    use std::sync::Arc;
-    #[test]
-    #[allow(unused_must_use)]
-    fn tset_tulip_interest_rates() {
-        // tests deriving usd price of SOL by the following route
-        // SOL -> RAY, RAY -> USDC
-        let mut config = Configuration::default();
-        config.init_log(false);
-        let rpc = Arc::new(RpcClient::new("https://ssc-dao.genesysgo.net".to_string()));
-        config.analytics.interest_rates.assets.push(AssetRate {
-            asset: "USDC".to_string(),
+    use crate::rate_lookup::account_source::SnapshotAccountSource;
+    use common::math::decimal::Decimal;
+    use config::analytics::interest_rates::SplLendingConfiguration;
+    use tulip_state::lending_reserve::{Reserve, ReserveConfig, ReserveLiquidity};
+
+    /// the slot the test snapshots were taken at
+    const SNAPSHOT_SLOT: u64 = 420;
+
+    /// returns the configuration of a tulip usdc reserve stored at `reserve`
+    fn tulip_rate(asset: &str, reserve: Pubkey) -> AssetRate {
+        AssetRate {
+            asset: asset.to_string(),
             platform: Platform::Tulip,
             program_id: common::tulip::LENDING_PROGRAM_ID.to_string(),
             spl_lending_config: Some(SplLendingConfiguration {
-                reserve: common::tulip::USDC_RESERVE_ACCOUNT.to_string(),
-                pyth_oracle: common::tulip::USDC_PYTH_PRICE_ACCOUNT.to_string(),
+                reserve: reserve.to_string(),
+                pyth_oracle: Pubkey::new_unique().to_string(),
                 switchboard_oracle: None,
                 solend_rate_args: None,
                 tulip_rate_args: None,
             }),
             mango_config: None,
             decimals: 6,
-        });
-        let interest_sample = interest_rate(
-            &Arc::new(config),
-            &rpc,
-            "USDC",
-            &Platform::Tulip.to_string(),
-        )
-        .unwrap();
-        println!("rate sample {:#?}", interest_sample);
+        }
+    }
+
+    /// returns a snapshot containing a tulip reserve at `reserve` with 300 tokens
+    /// available and 100 borrowed, which was refreshed at the snapshot slot
+    fn tulip_snapshot(reserve: Pubkey) -> SnapshotAccountSource {
+        let mut tulip_reserve = Reserve {
+            liquidity: ReserveLiquidity {
+                available_amount: 300,
+                borrowed_amount_wads: Decimal::from(100_u64),
+                mint_decimals: 6,
+                platform_fees: 10,
+                ..Default::default()
+            },
+            config: ReserveConfig {
+                optimal_utilization_rate: 50,
+                degen_utilization_rate: 90,
+                optimal_borrow_rate: 20,
+                degen_borrow_rate: 50,
+                max_borrow_rate: 150,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        tulip_reserve.last_update.slot = SNAPSHOT_SLOT;
+        let mut data = vec![0_u8; Reserve::LEN];
+        Reserve::pack(tulip_reserve, &mut data).unwrap();
+        let mut source = SnapshotAccountSource {
+            slot: SNAPSHOT_SLOT,
+            ..Default::default()
+        };
+        source.accounts.insert(
+            reserve,
+            Account {
+                lamports: 1_000_000,
+                data,
+                owner: common::tulip::LENDING_PROGRAM_ID,
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        source
     }
-    #[test]
-    #[allow(unused_must_use)]
-    fn tset_mango_interest_rates() {
-        // tests deriving usd price of SOL by the following route
-        // SOL -> RAY, RAY -> USDC
+
+    #[tokio::test]
+    async fn test_interest_rate_nonblocking() {
+        let reserve = Pubkey::new_unique();
         let mut config = Configuration::default();
-        config.init_log(false);
-        let rpc = Arc::new(RpcClient::new("https://ssc-dao.genesysgo.net".to_string()));
-        config.analytics.interest_rates.assets.push(AssetRate {
-            asset: "USDC".to_string(),
-            platform: Platform::MangoV3,
-            program_id: common::mango::MANGO_V3_PROGRAM_ID.to_string(),
-            spl_lending_config: None,
-            mango_config: Some(MangoConfiguration {
-                group: common::mango::MANGO_V3_GROUP_KEY.to_string(),
-                cache: common::mango::MANGO_V3_CACHE.to_string(),
-                root_bank: common::mango::MANGO_V3_MAINNET_ONE_USDC_ROOT_KEY.to_string(),
-                node_bank: common::mango::MANGO_V3_MAINNET_ONE_USDC_NODE_KEYS[0].to_string(),
-                group_token_account: common::mango::MANGO_V3_USDC_TOKEN_VAULT.to_string(),
-                rate_args: Default::default(),
-            }),
-            decimals: 6,
-        });
-        let interest_sample = interest_rate(
-            &Arc::new(config),
-            &rpc,
-            "USDC",
-            &Platform::MangoV3.to_string(),
-        )
-        .unwrap();
-        println!("rate sample {:#?}", interest_sample);
+        config
+            .analytics
+            .interest_rates
+            .assets
+            .push(tulip_rate("USDC", reserve));
+        let config = Arc::new(config);
+        let source = tulip_snapshot(reserve);
+        let platform = Platform::Tulip.to_string();
+
+        let sample = interest_rate_nonblocking(&config, &source, "USDC", &platform)
+            .await
+            .unwrap();
+        assert_eq!(sample.asset, "USDC");
+        assert_eq!(sample.platform, platform);
+        assert_eq!(sample.slot, SNAPSHOT_SLOT);
+        assert_eq!(sample.stale_slots, 0);
+        assert!(!sample.projected);
+        assert!((sample.available_amount - 0.0003).abs() < 1e-12);
+        assert!((sample.borrowed_amount - 0.0001).abs() < 1e-12);
+        // 25% utilization is half way to the 50% optimal utilization, so the borrow
+        // rate is half of the 20% optimal borrow rate
+        assert!((sample.utilization_rate - 25_f64).abs() < 1e-9);
+        assert!((sample.rate - 10_f64).abs() < 1e-9);
+        assert!((sample.interest_rate - 2.5).abs() < 1e-9);
+        assert!((sample.net_interest_rate - 2.25).abs() < 1e-9);
+
+        // replaying the same snapshot yields the same sample
+        let replayed = interest_rate_nonblocking(&config, &source, "USDC", &platform)
+            .await
+            .unwrap();
+        assert_eq!(format!("{:?}", replayed), format!("{:?}", sample));
+
+        // the lookup fails when the reserve is missing from the snapshot
+        let empty = SnapshotAccountSource::default();
+        assert!(matches!(
+            interest_rate_nonblocking(&config, &empty, "USDC", &platform).await,
+            Err(LookupError::Decode(_))
+        ));
+        // or when the asset isn't configured
+        assert!(matches!(
+            interest_rate_nonblocking(&config, &source, "SOL", &platform).await,
+            Err(LookupError::Config(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_interest_rates_batched() {
+        let reserve = Pubkey::new_unique();
+        let mut config = Configuration::default();
+        let mut invalid = tulip_rate("RAY", reserve);
+        if let Some(spl_config) = invalid.spl_lending_config.as_mut() {
+            spl_config.reserve = "not a pubkey".to_string();
+        }
+        config.analytics.interest_rates.assets = vec![
+            tulip_rate("USDC", reserve),
+            invalid,
+            tulip_rate("SOL", Pubkey::new_unique()),
+        ];
+        let (slot, results) = interest_rates_batched(&Arc::new(config), &tulip_snapshot(reserve))
+            .await
+            .unwrap();
+        assert_eq!(slot, SNAPSHOT_SLOT);
+        assert_eq!(results.len(), 3);
+        // an asset whose account keys can't be derived doesn't fail the batch
+        assert_eq!(results[0].0.asset, "USDC");
+        let sample = results[0].1.as_ref().unwrap();
+        assert!((sample.utilization_rate - 25_f64).abs() < 1e-9);
+        assert_eq!(results[1].0.asset, "RAY");
+        assert!(matches!(results[1].1, Err(LookupError::Config(_))));
+        assert!(results[1]
+            .1
+            .as_ref()
+            .unwrap_err()
+            .to_string()
+            .contains("failed to derive account keys"));
+        // the reserve isn't in the snapshot
+        assert_eq!(results[2].0.asset, "SOL");
+        assert!(matches!(results[2].1, Err(LookupError::Decode(_))));
     }
 }
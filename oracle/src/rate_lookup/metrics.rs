@@ -0,0 +1,44 @@
+//! prometheus metrics for interest rate lookups, registered with the default registry
+
+use once_cell::sync::Lazy;
+use prometheus::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec};
+
+use std::time::Instant;
+
+pub const OUTCOME_SUCCESS: &str = "success";
+pub const OUTCOME_ERROR: &str = "error";
+pub const OUTCOME_TIMEOUT: &str = "timeout";
+
+/// number of interest rate lookups, labelled by platform, asset and outcome
+pub static RATE_LOOKUPS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "oracle_rate_lookups_total",
+        "number of interest rate lookups by platform, asset and outcome",
+        &["platform", "asset", "outcome"]
+    )
+    .unwrap()
+});
+
+/// latency of rpc requests in seconds, labelled by rpc method
+pub static RPC_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "oracle_rpc_latency_seconds",
+        "latency of rpc requests by method",
+        &["method"]
+    )
+    .unwrap()
+});
+
+/// records the outcome of a single interest rate lookup
+pub fn observe_lookup(platform: &str, asset: &str, outcome: &str) {
+    RATE_LOOKUPS
+        .with_label_values(&[platform, asset, outcome])
+        .inc();
+}
+
+/// records the latency of an rpc request which began at `start`
+pub fn observe_rpc_latency(method: &str, start: Instant) {
+    RPC_LATENCY
+        .with_label_values(&[method])
+        .observe(start.elapsed().as_secs_f64());
+}
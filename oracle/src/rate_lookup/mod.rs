@@ -1,3 +1,10 @@
 //! provides interest rate lookup, and calculation functions needed for oracle functions
+pub mod account_source;
+pub mod adapter;
+pub mod allocation;
 pub mod compute;
 pub mod lookup;
+pub mod metrics;
+pub mod retry;
+pub mod rpc_pool;
+pub mod simulate;
@@ -0,0 +1,438 @@
+//! provides an `AccountSource` wrapper which classifies rpc errors, retrying transient
+//! and rate limited failures with jittered exponential backoff against a shared retry
+//! budget. requests which still fail once retries are exhausted are collected in a
+//! dead-letter list which the caller can inspect, and retry at a later time
+
+use anchor_client::solana_client::client_error::{ClientError, ClientErrorKind};
+use anchor_client::solana_client::rpc_config::RpcProgramAccountsConfig;
+use anchor_client::solana_client::rpc_request::RpcError;
+use anchor_client::solana_sdk::account::Account;
+use anchor_client::solana_sdk::commitment_config::CommitmentConfig;
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use anyhow::Result;
+use async_trait::async_trait;
+use log::warn;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::rate_lookup::account_source::AccountSource;
+
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// http status code returned by rpc nodes when rate limiting requests
+const HTTP_TOO_MANY_REQUESTS: u16 = 429;
+/// json-rpc error code returned when a node is unhealthy, or behind
+const JSON_RPC_NODE_UNHEALTHY: i64 = -32005;
+
+/// broad classification of an error returned by an account source
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// the request may succeed if retried, for example timeouts, or connection failures
+    Transient,
+    /// the node is rate limiting requests, retries should back off more aggressively
+    RateLimited,
+    /// the request will never succeed, for example decode errors, or invalid params
+    Permanent,
+}
+
+impl ErrorClass {
+    pub fn is_retryable(&self) -> bool {
+        !matches!(self, ErrorClass::Permanent)
+    }
+}
+
+/// classifies the given error. errors which did not originate from the
+/// rpc client are considered permanent
+pub fn classify_error(err: &anyhow::Error) -> ErrorClass {
+    match err.downcast_ref::<ClientError>() {
+        Some(client_error) => classify_client_error(client_error),
+        None => ErrorClass::Permanent,
+    }
+}
+
+pub fn classify_client_error(err: &ClientError) -> ErrorClass {
+    match err.kind() {
+        ClientErrorKind::Io(_) => ErrorClass::Transient,
+        ClientErrorKind::Reqwest(err) => match err.status() {
+            Some(status) if status.as_u16() == HTTP_TOO_MANY_REQUESTS => ErrorClass::RateLimited,
+            Some(status) if status.is_server_error() => ErrorClass::Transient,
+            Some(_) => ErrorClass::Permanent,
+            None if err.is_timeout() || err.is_connect() || err.is_request() => {
+                ErrorClass::Transient
+            }
+            None => ErrorClass::Permanent,
+        },
+        ClientErrorKind::RpcError(RpcError::RpcRequestError(_)) => ErrorClass::Transient,
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. }) => {
+            if *code == JSON_RPC_NODE_UNHEALTHY {
+                ErrorClass::Transient
+            } else {
+                ErrorClass::Permanent
+            }
+        }
+        ClientErrorKind::RpcError(RpcError::ForUser(msg)) => {
+            // the rpc client surfaces some http errors as plain messages
+            if msg.contains(&HTTP_TOO_MANY_REQUESTS.to_string()) {
+                ErrorClass::RateLimited
+            } else {
+                ErrorClass::Permanent
+            }
+        }
+        _ => ErrorClass::Permanent,
+    }
+}
+
+/// controls how failed requests are retried
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct RetryPolicy {
+    /// maximum number of retries for a single request
+    pub max_retries: u32,
+    /// maximum number of retries shared by all requests made through the source,
+    /// until the budget is reset. this prevents a broken node from stalling a job
+    /// by having every request retry `max_retries` times
+    pub budget: u32,
+    /// delay before the first retry, doubled with each subsequent retry
+    pub base_delay_ms: u64,
+    /// upper bound of the delay between retries
+    pub max_delay_ms: u64,
+    /// lower bound of the delay between retries of rate limited requests
+    pub rate_limited_delay_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 4,
+            budget: 50,
+            base_delay_ms: 250,
+            max_delay_ms: 10_000,
+            rate_limited_delay_ms: 2_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// returns the delay before retry number `attempt` (starting at 0). the delay
+    /// is chosen uniformly between 0 and the exponential backoff ("full jitter"), so
+    /// that concurrent requests which failed together don't retry in lock step
+    pub fn backoff(&self, attempt: u32, class: ErrorClass) -> Duration {
+        let exponential = self
+            .base_delay_ms
+            .saturating_mul(2_u64.saturating_pow(attempt))
+            .min(self.max_delay_ms);
+        let jittered = rand::thread_rng().gen_range(0..=exponential);
+        let delay = if class == ErrorClass::RateLimited {
+            jittered.max(self.rate_limited_delay_ms)
+        } else {
+            jittered
+        };
+        Duration::from_millis(delay)
+    }
+}
+
+/// a request made through an account source
+#[derive(Clone, Debug)]
+pub enum AccountRequest {
+    GetAccount(Pubkey),
+    GetMultipleAccounts(Vec<Pubkey>),
+    GetProgramAccounts(Pubkey, RpcProgramAccountsConfig),
+}
+
+/// the response to an `AccountRequest`
+#[derive(Clone, Debug)]
+pub enum AccountResponse {
+    Account(Option<Account>),
+    MultipleAccounts(u64, Vec<Option<Account>>),
+    ProgramAccounts(u64, Vec<(Pubkey, Account)>),
+}
+
+impl AccountRequest {
+    /// sends the request using the given account source
+    pub async fn send(&self, source: &dyn AccountSource) -> Result<AccountResponse> {
+        Ok(match self {
+            AccountRequest::GetAccount(key) => {
+                AccountResponse::Account(source.get_account(key).await?)
+            }
+            AccountRequest::GetMultipleAccounts(keys) => {
+                let (slot, accounts) = source.get_multiple_accounts(keys).await?;
+                AccountResponse::MultipleAccounts(slot, accounts)
+            }
+            AccountRequest::GetProgramAccounts(program_id, config) => {
+                let (slot, accounts) = source
+                    .get_program_accounts(program_id, config.clone())
+                    .await?;
+                AccountResponse::ProgramAccounts(slot, accounts)
+            }
+        })
+    }
+}
+
+/// a request which failed with a retryable error after retries were exhausted
+#[derive(Clone, Debug)]
+pub struct DeadLetter {
+    pub request: AccountRequest,
+    pub class: ErrorClass,
+    pub error: String,
+}
+
+/// wraps an account source, retrying failed requests according to a `RetryPolicy`
+pub struct RetryingAccountSource<S: AccountSource> {
+    pub inner: S,
+    pub policy: RetryPolicy,
+    remaining_budget: AtomicU32,
+    dead_letters: Mutex<Vec<DeadLetter>>,
+}
+
+impl<S: AccountSource> RetryingAccountSource<S> {
+    pub fn new(inner: S, policy: RetryPolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            remaining_budget: AtomicU32::new(policy.budget),
+            dead_letters: Mutex::new(Vec::new()),
+        }
+    }
+    /// restores the retry budget, typically called at the start of each job run
+    pub fn reset_budget(&self) {
+        self.remaining_budget
+            .store(self.policy.budget, Ordering::SeqCst);
+    }
+    /// returns the number of retries left in the budget
+    pub fn remaining_budget(&self) -> u32 {
+        self.remaining_budget.load(Ordering::SeqCst)
+    }
+    /// returns a copy of the dead-letter list
+    pub fn dead_letters(&self) -> Vec<DeadLetter> {
+        match self.dead_letters.lock() {
+            Ok(dead_letters) => dead_letters.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+    /// removes and returns all dead letters
+    pub fn take_dead_letters(&self) -> Vec<DeadLetter> {
+        match self.dead_letters.lock() {
+            Ok(mut dead_letters) => std::mem::take(&mut *dead_letters),
+            Err(poisoned) => std::mem::take(&mut *poisoned.into_inner()),
+        }
+    }
+    /// takes a single retry from the budget, returning false if it is exhausted
+    fn take_budget(&self) -> bool {
+        self.remaining_budget
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |remaining| {
+                remaining.checked_sub(1)
+            })
+            .is_ok()
+    }
+    fn push_dead_letter(&self, dead_letter: DeadLetter) {
+        match self.dead_letters.lock() {
+            Ok(mut dead_letters) => dead_letters.push(dead_letter),
+            Err(poisoned) => poisoned.into_inner().push(dead_letter),
+        }
+    }
+    /// sends the request, retrying retryable errors until the request succeeds,
+    /// `max_retries` is reached, or the retry budget is exhausted
+    pub async fn send(&self, request: AccountRequest) -> Result<AccountResponse> {
+        self.retry(request.clone(), || request.send(&self.inner))
+            .await
+    }
+    /// calls `f` until it succeeds, retrying retryable errors until `max_retries` is
+    /// reached, or the retry budget is exhausted. `request` describes the call made
+    /// by `f`, and is recorded in the dead-letter list if the call is given up on
+    async fn retry<T, F, Fut>(&self, request: AccountRequest, f: F) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let err = match f().await {
+                Ok(response) => return Ok(response),
+                Err(err) => err,
+            };
+            let class = classify_error(&err);
+            if !class.is_retryable() {
+                return Err(err);
+            }
+            if attempt >= self.policy.max_retries || !self.take_budget() {
+                warn!(
+                    "giving up on {:?} request after {} retries: {:#}",
+                    class, attempt, err
+                );
+                self.push_dead_letter(DeadLetter {
+                    request,
+                    class,
+                    error: err.to_string(),
+                });
+                return Err(err);
+            }
+            tokio::time::sleep(self.policy.backoff(attempt, class)).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[async_trait]
+impl<S: AccountSource> AccountSource for RetryingAccountSource<S> {
+    fn commitment(&self) -> CommitmentConfig {
+        self.inner.commitment()
+    }
+    async fn get_account(&self, key: &Pubkey) -> Result<Option<Account>> {
+        self.retry(AccountRequest::GetAccount(*key), || {
+            self.inner.get_account(key)
+        })
+        .await
+    }
+    async fn get_multiple_accounts(&self, keys: &[Pubkey]) -> Result<(u64, Vec<Option<Account>>)> {
+        self.retry(AccountRequest::GetMultipleAccounts(keys.to_vec()), || {
+            self.inner.get_multiple_accounts(keys)
+        })
+        .await
+    }
+    async fn get_program_accounts(
+        &self,
+        program_id: &Pubkey,
+        config: RpcProgramAccountsConfig,
+    ) -> Result<(u64, Vec<(Pubkey, Account)>)> {
+        self.retry(
+            AccountRequest::GetProgramAccounts(*program_id, config.clone()),
+            || self.inner.get_program_accounts(program_id, config.clone()),
+        )
+        .await
+    }
+    /// quorum reads are used to cross check other reads, so they are not retried
+    async fn get_multiple_accounts_quorum(
+        &self,
+        keys: &[Pubkey],
+        count: usize,
+    ) -> Result<Vec<(u64, Vec<Option<Account>>)>> {
+        self.inner.get_multiple_accounts_quorum(keys, count).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io;
+    use std::sync::atomic::AtomicUsize;
+
+    /// fails the first `failures` requests with the given error kind
+    struct FlakySource {
+        failures: usize,
+        calls: AtomicUsize,
+        permanent: bool,
+    }
+
+    #[async_trait]
+    impl AccountSource for FlakySource {
+        fn commitment(&self) -> CommitmentConfig {
+            CommitmentConfig::default()
+        }
+        async fn get_account(&self, _key: &Pubkey) -> Result<Option<Account>> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                if self.permanent {
+                    return Err(anyhow::anyhow!("failed to decode account"));
+                }
+                return Err(
+                    ClientError::from(io::Error::new(io::ErrorKind::Other, "timeout")).into(),
+                );
+            }
+            Ok(Some(Account::default()))
+        }
+        async fn get_multiple_accounts(
+            &self,
+            _keys: &[Pubkey],
+        ) -> Result<(u64, Vec<Option<Account>>)> {
+            Err(anyhow::anyhow!("get_multiple_accounts is unsupported"))
+        }
+        async fn get_program_accounts(
+            &self,
+            _program_id: &Pubkey,
+            _config: RpcProgramAccountsConfig,
+        ) -> Result<(u64, Vec<(Pubkey, Account)>)> {
+            Err(anyhow::anyhow!("get_program_accounts is unsupported"))
+        }
+    }
+
+    fn test_policy(budget: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            budget,
+            base_delay_ms: 1,
+            max_delay_ms: 2,
+            rate_limited_delay_ms: 2,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retrying_account_source() {
+        // transient errors are retried until success
+        let source = RetryingAccountSource::new(
+            FlakySource {
+                failures: 2,
+                calls: AtomicUsize::new(0),
+                permanent: false,
+            },
+            test_policy(10),
+        );
+        assert!(source
+            .get_account(&Pubkey::new_unique())
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(source.remaining_budget(), 8);
+        assert!(source.dead_letters().is_empty());
+
+        // permanent errors are never retried, or dead lettered
+        let source = RetryingAccountSource::new(
+            FlakySource {
+                failures: 1,
+                calls: AtomicUsize::new(0),
+                permanent: true,
+            },
+            test_policy(10),
+        );
+        assert!(source.get_account(&Pubkey::new_unique()).await.is_err());
+        assert_eq!(source.inner.calls.load(Ordering::SeqCst), 1);
+        assert!(source.dead_letters().is_empty());
+
+        // an exhausted budget dead letters the request
+        let source = RetryingAccountSource::new(
+            FlakySource {
+                failures: 10,
+                calls: AtomicUsize::new(0),
+                permanent: false,
+            },
+            test_policy(1),
+        );
+        let key = Pubkey::new_unique();
+        assert!(source.get_account(&key).await.is_err());
+        assert_eq!(source.inner.calls.load(Ordering::SeqCst), 2);
+        let dead_letters = source.take_dead_letters();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].class, ErrorClass::Transient);
+        assert!(matches!(
+            dead_letters[0].request,
+            AccountRequest::GetAccount(dead_key) if dead_key == key
+        ));
+        assert!(source.dead_letters().is_empty());
+
+        // errors from the typed calls are returned unchanged
+        let err = source.get_multiple_accounts(&[key]).await.unwrap_err();
+        assert_eq!(err.to_string(), "get_multiple_accounts is unsupported");
+        assert!(source.dead_letters().is_empty());
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::default();
+        for attempt in 0..10 {
+            let delay = policy.backoff(attempt, ErrorClass::Transient);
+            assert!(delay <= Duration::from_millis(policy.max_delay_ms));
+            let delay = policy.backoff(attempt, ErrorClass::RateLimited);
+            assert!(delay >= Duration::from_millis(policy.rate_limited_delay_ms));
+        }
+    }
+}
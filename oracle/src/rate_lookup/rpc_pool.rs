@@ -0,0 +1,315 @@
+//! provides `RpcPool`, an account source spread across multiple rpc endpoints which
+//! tracks the slot lag and error rate of each endpoint, routing requests to healthy
+//! endpoints and failing over to the next endpoint when a request fails
+
+use anchor_client::solana_client::nonblocking::rpc_client::RpcClient;
+use anchor_client::solana_client::rpc_config::RpcProgramAccountsConfig;
+use anchor_client::solana_sdk::account::Account;
+use anchor_client::solana_sdk::commitment_config::CommitmentConfig;
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::rate_lookup::account_source::{AccountSource, RpcAccountSource};
+
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// weight given to the most recent request when updating an endpoint's error rate
+const ERROR_RATE_ALPHA: f64 = 0.2;
+
+/// controls when an endpoint is considered healthy
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct RpcPoolOpts {
+    /// endpoints more than this many slots behind the most recent slot
+    /// observed across all endpoints are considered unhealthy
+    pub max_slot_lag: u64,
+    /// endpoints with a moving average error rate above this are considered unhealthy
+    pub max_error_rate: f64,
+    /// how often the slot of each endpoint is refreshed
+    pub health_check_interval_secs: u64,
+}
+
+impl Default for RpcPoolOpts {
+    fn default() -> Self {
+        Self {
+            max_slot_lag: 50,
+            max_error_rate: 0.5,
+            health_check_interval_secs: 30,
+        }
+    }
+}
+
+/// health information tracked for each endpoint
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EndpointHealth {
+    /// the most recent slot observed from the endpoint
+    pub slot: u64,
+    /// exponential moving average of the fraction of requests which failed
+    pub error_rate: f64,
+}
+
+struct PoolEndpoint {
+    url: String,
+    source: RpcAccountSource,
+    health: Mutex<EndpointHealth>,
+}
+
+impl PoolEndpoint {
+    fn health(&self) -> EndpointHealth {
+        match self.health.lock() {
+            Ok(health) => *health,
+            Err(poisoned) => *poisoned.into_inner(),
+        }
+    }
+    fn update_health(&self, update: impl FnOnce(&mut EndpointHealth)) {
+        match self.health.lock() {
+            Ok(mut health) => update(&mut health),
+            Err(poisoned) => update(&mut poisoned.into_inner()),
+        }
+    }
+    fn observe_result<T>(&self, result: &Result<T>, slot: Option<u64>) {
+        self.update_health(|health| {
+            let failed = if result.is_err() { 1_f64 } else { 0_f64 };
+            health.error_rate =
+                health.error_rate * (1_f64 - ERROR_RATE_ALPHA) + failed * ERROR_RATE_ALPHA;
+            if let Some(slot) = slot {
+                health.slot = health.slot.max(slot);
+            }
+        });
+    }
+}
+
+/// an account source which routes requests across multiple rpc endpoints
+pub struct RpcPool {
+    endpoints: Vec<PoolEndpoint>,
+    commitment: CommitmentConfig,
+    opts: RpcPoolOpts,
+    last_health_check: Mutex<Option<Instant>>,
+    /// rotates the starting endpoint amongst equally healthy endpoints
+    next: AtomicUsize,
+}
+
+impl RpcPool {
+    pub fn new(urls: &[String], commitment: CommitmentConfig, opts: RpcPoolOpts) -> Result<Self> {
+        if urls.is_empty() {
+            return Err(anyhow!("rpc pool requires at least one endpoint"));
+        }
+        Ok(Self {
+            endpoints: urls
+                .iter()
+                .map(|url| PoolEndpoint {
+                    url: url.clone(),
+                    source: RpcAccountSource::new(Arc::new(RpcClient::new_with_commitment(
+                        url.clone(),
+                        commitment,
+                    ))),
+                    health: Mutex::new(EndpointHealth::default()),
+                })
+                .collect(),
+            commitment,
+            opts,
+            last_health_check: Mutex::new(None),
+            next: AtomicUsize::new(0),
+        })
+    }
+    /// returns the url and health of every endpoint
+    pub fn health(&self) -> Vec<(String, EndpointHealth)> {
+        self.endpoints
+            .iter()
+            .map(|endpoint| (endpoint.url.clone(), endpoint.health()))
+            .collect()
+    }
+    /// refreshes the slot of every endpoint
+    pub async fn refresh_health(&self) {
+        for endpoint in self.endpoints.iter() {
+            let result: Result<u64> = endpoint.source.rpc.get_slot().await.map_err(Into::into);
+            let slot = result.as_ref().ok().copied();
+            if let Err(err) = &result {
+                warn!("health check failed for {}: {:#}", endpoint.url, err);
+            }
+            endpoint.observe_result(&result, slot);
+        }
+        if let Ok(mut last_health_check) = self.last_health_check.lock() {
+            *last_health_check = Some(Instant::now());
+        }
+    }
+    async fn maybe_refresh_health(&self) {
+        let stale = match self.last_health_check.lock() {
+            Ok(last_health_check) => match *last_health_check {
+                Some(checked_at) => {
+                    checked_at.elapsed()
+                        >= Duration::from_secs(self.opts.health_check_interval_secs)
+                }
+                None => true,
+            },
+            Err(_) => true,
+        };
+        if stale {
+            self.refresh_health().await;
+        }
+    }
+    /// returns the endpoint indexes in the order requests should be attempted. healthy
+    /// endpoints are attempted first, rotating between them, followed by unhealthy
+    /// endpoints ordered by their slot lag
+    fn routing_order(&self) -> Vec<usize> {
+        let healths: Vec<EndpointHealth> = self.endpoints.iter().map(|e| e.health()).collect();
+        let max_slot = healths.iter().map(|h| h.slot).max().unwrap_or_default();
+        let is_healthy = |health: &EndpointHealth| {
+            max_slot.saturating_sub(health.slot) <= self.opts.max_slot_lag
+                && health.error_rate <= self.opts.max_error_rate
+        };
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let count = self.endpoints.len();
+        let (mut healthy, mut unhealthy): (Vec<usize>, Vec<usize>) = (0..count)
+            .map(|offset| (start + offset) % count)
+            .partition(|idx| is_healthy(&healths[*idx]));
+        unhealthy.sort_by_key(|idx| max_slot.saturating_sub(healths[*idx].slot));
+        healthy.append(&mut unhealthy);
+        healthy
+    }
+    /// sends a request to endpoints in routing order, until one succeeds
+    async fn route<'a, T, F, Fut>(&'a self, method: &str, request: F) -> Result<T>
+    where
+        F: Fn(&'a PoolEndpoint) -> Fut,
+        Fut: Future<Output = (Result<T>, Option<u64>)>,
+    {
+        self.maybe_refresh_health().await;
+        let mut last_error = None;
+        for idx in self.routing_order() {
+            let endpoint = &self.endpoints[idx];
+            let (result, slot) = request(endpoint).await;
+            endpoint.observe_result(&result, slot);
+            match result {
+                Ok(value) => return Ok(value),
+                Err(err) => {
+                    debug!(
+                        "{} failed on {}, failing over: {:#}",
+                        method, endpoint.url, err
+                    );
+                    last_error = Some(err);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow!("no endpoints available")))
+    }
+}
+
+#[async_trait]
+impl AccountSource for RpcPool {
+    fn commitment(&self) -> CommitmentConfig {
+        self.commitment
+    }
+    async fn get_account(&self, key: &Pubkey) -> Result<Option<Account>> {
+        self.route("getAccountInfo", |endpoint| async move {
+            (endpoint.source.get_account(key).await, None)
+        })
+        .await
+    }
+    async fn get_multiple_accounts(&self, keys: &[Pubkey]) -> Result<(u64, Vec<Option<Account>>)> {
+        self.route("getMultipleAccounts", |endpoint| async move {
+            let result = endpoint.source.get_multiple_accounts(keys).await;
+            let slot = result.as_ref().ok().map(|(slot, _)| *slot);
+            (result, slot)
+        })
+        .await
+    }
+    async fn get_program_accounts(
+        &self,
+        program_id: &Pubkey,
+        config: RpcProgramAccountsConfig,
+    ) -> Result<(u64, Vec<(Pubkey, Account)>)> {
+        let config = &config;
+        self.route("getProgramAccounts", |endpoint| async move {
+            let result = endpoint
+                .source
+                .get_program_accounts(program_id, config.clone())
+                .await;
+            let slot = result.as_ref().ok().map(|(slot, _)| *slot);
+            (result, slot)
+        })
+        .await
+    }
+    /// fetches the accounts from up to `count` distinct endpoints, in routing order
+    async fn get_multiple_accounts_quorum(
+        &self,
+        keys: &[Pubkey],
+        count: usize,
+    ) -> Result<Vec<(u64, Vec<Option<Account>>)>> {
+        self.maybe_refresh_health().await;
+        let mut responses = Vec::with_capacity(count);
+        for idx in self.routing_order() {
+            if responses.len() >= count {
+                break;
+            }
+            let endpoint = &self.endpoints[idx];
+            let result = endpoint.source.get_multiple_accounts(keys).await;
+            let slot = result.as_ref().ok().map(|(slot, _)| *slot);
+            endpoint.observe_result(&result, slot);
+            match result {
+                Ok(response) => responses.push(response),
+                Err(err) => warn!("quorum read failed on {}: {:#}", endpoint.url, err),
+            }
+        }
+        if responses.is_empty() {
+            return Err(anyhow!("quorum read failed on every endpoint"));
+        }
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_routing_order() {
+        let urls = vec![
+            "http://localhost:1".to_string(),
+            "http://localhost:2".to_string(),
+            "http://localhost:3".to_string(),
+        ];
+        let pool =
+            RpcPool::new(&urls, CommitmentConfig::confirmed(), RpcPoolOpts::default()).unwrap();
+        pool.endpoints[0].update_health(|health| health.slot = 1_000);
+        // lagging by more than max_slot_lag
+        pool.endpoints[1].update_health(|health| health.slot = 900);
+        // failing too many requests
+        pool.endpoints[2].update_health(|health| {
+            health.slot = 1_000;
+            health.error_rate = 0.9;
+        });
+        let order = pool.routing_order();
+        assert_eq!(order[0], 0);
+        // unhealthy endpoints are ordered by slot lag
+        assert_eq!(&order[1..], &[2, 1]);
+
+        pool.endpoints[2].update_health(|health| health.error_rate = 0.0);
+        let mut firsts = Vec::new();
+        for _ in 0..4 {
+            let order = pool.routing_order();
+            assert_eq!(*order.last().unwrap(), 1);
+            firsts.push(order[0]);
+        }
+        // healthy endpoints are rotated between
+        assert!(firsts.contains(&0));
+        assert!(firsts.contains(&2));
+    }
+
+    #[test]
+    fn test_error_rate() {
+        let urls = vec!["http://localhost:1".to_string()];
+        let pool =
+            RpcPool::new(&urls, CommitmentConfig::confirmed(), RpcPoolOpts::default()).unwrap();
+        let endpoint = &pool.endpoints[0];
+        endpoint.observe_result::<()>(&Err(anyhow!("failed")), None);
+        assert!((endpoint.health().error_rate - ERROR_RATE_ALPHA).abs() < f64::EPSILON);
+        endpoint.observe_result(&Ok(()), Some(10));
+        assert!(endpoint.health().error_rate < ERROR_RATE_ALPHA);
+        assert_eq!(endpoint.health().slot, 10);
+    }
+}
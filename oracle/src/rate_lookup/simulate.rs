@@ -0,0 +1,263 @@
+//! what-if simulation of lending reserve rates, projecting how depositing or withdrawing
+//! liquidity would change a reserve's utilization, borrow rate and supply rate. spl style
+//! reserves are simulated by applying the delta to a copy of the reserve, so each platform's
+//! own rate curve logic is reused rather than reimplemented
+
+use crate::rate_lookup::adapter::{LendingReserveAdapter, MangoReserve};
+use anyhow::{anyhow, Result};
+use common::mango::mango_lib::state::RootBank;
+use common::port::port_variable_rate_lending_instructions::state as port_state;
+use common::solend::solend_token_lending::state as solend_state;
+use common::tulip as tulip_state;
+use fixed::types::I80F48;
+
+/// the projected rates of a reserve, as fractions so a borrow rate of 0.1 is 10%
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RateProjection {
+    pub utilization_rate: f64,
+    pub borrow_rate: f64,
+    pub supply_rate: f64,
+}
+
+/// projects the rates of a tulip reserve after depositing `liquidity_delta` native
+/// units of liquidity, or withdrawing them when negative
+pub fn simulate_tulip(
+    reserve: &tulip_state::lending_reserve::Reserve,
+    liquidity_delta: i64,
+) -> Result<RateProjection> {
+    let mut reserve = reserve.clone();
+    if liquidity_delta >= 0 {
+        reserve.liquidity.deposit(liquidity_delta as u64)?;
+    } else {
+        reserve.liquidity.withdraw(liquidity_delta.unsigned_abs())?;
+    }
+    projection(&reserve)
+}
+
+/// projects the rates of a solend reserve after depositing `liquidity_delta` native
+/// units of liquidity, or withdrawing them when negative
+pub fn simulate_solend(
+    reserve: &solend_state::Reserve,
+    liquidity_delta: i64,
+) -> Result<RateProjection> {
+    let mut reserve = reserve.clone();
+    if liquidity_delta >= 0 {
+        reserve.liquidity.deposit(liquidity_delta as u64)?;
+    } else {
+        reserve.liquidity.withdraw(liquidity_delta.unsigned_abs())?;
+    }
+    projection(&reserve)
+}
+
+/// projects the rates of a port reserve after depositing `liquidity_delta` native
+/// units of liquidity, or withdrawing them when negative
+pub fn simulate_port(
+    reserve: &port_state::Reserve,
+    liquidity_delta: i64,
+) -> Result<RateProjection> {
+    let mut reserve = reserve.clone();
+    if liquidity_delta >= 0 {
+        reserve.liquidity.deposit(liquidity_delta as u64)?;
+    } else {
+        reserve.liquidity.withdraw(liquidity_delta.unsigned_abs())?;
+    }
+    projection(&reserve)
+}
+
+/// projects the rates of a mango token after depositing `liquidity_delta` native units,
+/// or withdrawing them when negative. `total_native_deposit` and `total_native_borrow`
+/// are the node bank totals scaled by the cached deposit and borrow indexes, as computed
+/// by the interest rate lookup
+pub fn simulate_mango(
+    root_bank: &RootBank,
+    total_native_deposit: I80F48,
+    total_native_borrow: I80F48,
+    liquidity_delta: i64,
+) -> Result<RateProjection> {
+    let total_native_deposit = total_native_deposit
+        .checked_add(I80F48::from_num(liquidity_delta))
+        .ok_or_else(|| anyhow!("deposit overflow"))?;
+    if total_native_deposit < total_native_borrow {
+        return Err(anyhow!("withdrawal exceeds available liquidity"));
+    }
+    projection(&MangoReserve {
+        root_bank: *root_bank,
+        total_native_deposit,
+        total_native_borrow,
+        // decimals aren't needed to compute rates
+        decimals: 0,
+    })
+}
+
+fn projection(reserve: &dyn LendingReserveAdapter) -> Result<RateProjection> {
+    Ok(RateProjection {
+        utilization_rate: reserve.utilization_rate()?,
+        borrow_rate: reserve.borrow_rate()?,
+        supply_rate: reserve.supply_rate()?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use common::math::decimal::Decimal;
+    use common::port::port_variable_rate_lending_instructions::math::Decimal as PortDecimal;
+    use common::solend::solend_token_lending::math::Decimal as SolendDecimal;
+    use tulip_state::lending_reserve::{Reserve, ReserveConfig, ReserveLiquidity};
+
+    fn reserve() -> Reserve {
+        Reserve {
+            liquidity: ReserveLiquidity {
+                available_amount: 500,
+                borrowed_amount_wads: Decimal::from(500_u64),
+                ..Default::default()
+            },
+            config: ReserveConfig {
+                optimal_utilization_rate: 80,
+                degen_utilization_rate: 90,
+                min_borrow_rate: 0,
+                optimal_borrow_rate: 10,
+                degen_borrow_rate: 50,
+                max_borrow_rate: 150,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_simulate_tulip() {
+        let reserve = reserve();
+        let current = simulate_tulip(&reserve, 0).unwrap();
+        assert!((current.utilization_rate - 0.5).abs() < 1e-12);
+        assert!((current.borrow_rate - 0.0625).abs() < 1e-12);
+        assert!((current.supply_rate - 0.03125).abs() < 1e-12);
+        // the simulation doesn't modify the reserve
+        assert_eq!(reserve.liquidity.available_amount, 500);
+
+        // depositing lowers utilization, and therefore both rates
+        let deposit = simulate_tulip(&reserve, 500).unwrap();
+        assert!((deposit.utilization_rate - 500_f64 / 1500_f64).abs() < 1e-12);
+        assert!(deposit.borrow_rate < current.borrow_rate);
+        assert!(deposit.supply_rate < current.supply_rate);
+
+        // withdrawing into the degen region raises them steeply
+        let withdraw = simulate_tulip(&reserve, -450).unwrap();
+        assert!((withdraw.utilization_rate - 500_f64 / 550_f64).abs() < 1e-12);
+        assert!(withdraw.borrow_rate > 0.5);
+
+        // withdrawing more than is available fails
+        assert!(simulate_tulip(&reserve, -501).is_err());
+    }
+
+    /// asserts the projection matches the expected utilization, borrow and supply rates
+    fn assert_projection(projection: RateProjection, expected: (f64, f64, f64)) {
+        assert!((projection.utilization_rate - expected.0).abs() < 1e-12);
+        assert!((projection.borrow_rate - expected.1).abs() < 1e-12);
+        assert!((projection.supply_rate - expected.2).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_simulate_solend() {
+        let reserve = solend_state::Reserve {
+            liquidity: solend_state::ReserveLiquidity {
+                available_amount: 600,
+                borrowed_amount_wads: SolendDecimal::from(400_u64),
+                ..Default::default()
+            },
+            config: solend_state::ReserveConfig {
+                optimal_utilization_rate: 80,
+                min_borrow_rate: 0,
+                optimal_borrow_rate: 8,
+                max_borrow_rate: 100,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        // 40% utilization is half way to the 80% optimal utilization
+        assert_projection(simulate_solend(&reserve, 0).unwrap(), (0.4, 0.04, 0.016));
+        assert_eq!(reserve.liquidity.available_amount, 600);
+        // depositing 1000 halves utilization, and with it the borrow rate
+        assert_projection(simulate_solend(&reserve, 1000).unwrap(), (0.2, 0.02, 0.004));
+        // withdrawing 200 leaves 400 of 800 borrowed
+        assert_projection(simulate_solend(&reserve, -200).unwrap(), (0.5, 0.05, 0.025));
+        // withdrawing 550 moves past the optimal utilization, where the rate climbs
+        // from the 8% optimal rate toward the 100% max rate
+        let utilization = 400_f64 / 450_f64;
+        let borrow_rate = 0.08 + (utilization - 0.8) / 0.2 * 0.92;
+        assert_projection(
+            simulate_solend(&reserve, -550).unwrap(),
+            (utilization, borrow_rate, borrow_rate * utilization),
+        );
+        // withdrawing more than is available fails
+        assert!(simulate_solend(&reserve, -601).is_err());
+    }
+
+    #[test]
+    fn test_simulate_port() {
+        let reserve = port_state::Reserve {
+            liquidity: port_state::ReserveLiquidity {
+                available_amount: 750,
+                borrowed_amount_wads: PortDecimal::from(250_u64),
+                ..Default::default()
+            },
+            config: port_state::ReserveConfig {
+                optimal_utilization_rate: 50,
+                min_borrow_rate: 2,
+                optimal_borrow_rate: 12,
+                max_borrow_rate: 60,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        // 25% utilization is half way to the 50% optimal utilization, half way
+        // between the 2% min and 12% optimal borrow rates
+        assert_projection(simulate_port(&reserve, 0).unwrap(), (0.25, 0.07, 0.0175));
+        assert_eq!(reserve.liquidity.available_amount, 750);
+        // depositing 250 leaves 250 of 1250 borrowed
+        assert_projection(simulate_port(&reserve, 250).unwrap(), (0.2, 0.06, 0.012));
+        // withdrawing 500 leaves 250 of 500 borrowed, exactly the optimal utilization
+        assert_projection(simulate_port(&reserve, -500).unwrap(), (0.5, 0.12, 0.06));
+        // withdrawing 625 leaves 250 of 375 borrowed, a third of the way from the
+        // optimal utilization to full utilization
+        let utilization = 250_f64 / 375_f64;
+        let borrow_rate = 0.12 + (utilization - 0.5) / 0.5 * 0.48;
+        assert_projection(
+            simulate_port(&reserve, -625).unwrap(),
+            (utilization, borrow_rate, borrow_rate * utilization),
+        );
+        // withdrawing more than is available fails
+        assert!(simulate_port(&reserve, -751).is_err());
+    }
+
+    #[test]
+    fn test_simulate_mango() {
+        let mut root_bank: RootBank = bytemuck::Zeroable::zeroed();
+        root_bank.optimal_util = I80F48::from_num(0.7);
+        root_bank.optimal_rate = I80F48::from_num(0.06);
+        root_bank.max_rate = I80F48::from_num(1.5);
+        let deposits = I80F48::from_num(1000);
+        let borrows = I80F48::from_num(350);
+        // mango's fixed point rates aren't exact, so are compared with a looser tolerance
+        let assert_mango = |liquidity_delta: i64, expected: (f64, f64, f64)| {
+            let projection =
+                simulate_mango(&root_bank, deposits, borrows, liquidity_delta).unwrap();
+            assert!((projection.utilization_rate - expected.0).abs() < 1e-9);
+            assert!((projection.borrow_rate - expected.1).abs() < 1e-9);
+            assert!((projection.supply_rate - expected.2).abs() < 1e-9);
+        };
+        // below the optimal utilization the borrow rate rises linearly to the optimal
+        // rate, and compute_deposit_rate pays it on the utilized portion of deposits
+        assert_mango(0, (0.35, 0.03, 0.0105));
+        let utilization = 350_f64 / 1750_f64;
+        let borrow_rate = 0.06 / 0.7 * utilization;
+        assert_mango(750, (utilization, borrow_rate, borrow_rate * utilization));
+        // withdrawing 500 leaves 350 of 500 borrowed, exactly the optimal utilization
+        assert_mango(-500, (0.7, 0.06, 0.042));
+        // withdrawing everything that isn't borrowed reaches the max rate
+        assert_mango(-650, (1_f64, 1.5, 1.5));
+        // withdrawing more than the deposits which aren't borrowed fails
+        let err = simulate_mango(&root_bank, deposits, borrows, -651).unwrap_err();
+        assert_eq!(err.to_string(), "withdrawal exceeds available liquidity");
+    }
+}
@@ -0,0 +1,393 @@
+//! provides the scraper daemon, which runs scrape jobs on independent
+//! intervals until a shutdown is requested
+
+use crate::interest_rates::{scrape_interest_rates, InterestRateScrapeOpts};
+use crate::lending_optimizer::{scrape_lending_allocation, LendingAllocationOpts};
+use crate::report::ScrapeReport;
+use crate::reserve_configs::scrape_reserve_configs;
+use crate::scan::{ScanCache, ScanOpts};
+use crate::sink::{DbSink, Sink, StdoutSink};
+use crate::v1::liquidations::{scrape_liquidated_positions, LiquidationTracker};
+use crate::v1::{scrape_obligation_accounts, scrape_obligation_ltvs, scrape_user_farm};
+use anyhow::{anyhow, Result};
+use config::Configuration;
+use diesel::{Connection, PgConnection};
+use log::{error, info, warn};
+use oracle::rate_lookup::account_source::AccountSource;
+use oracle::rate_lookup::retry::{RetryPolicy, RetryingAccountSource};
+use oracle::rate_lookup::rpc_pool::{RpcPool, RpcPoolOpts};
+use serde::{Deserialize, Serialize};
+use solana_sdk::commitment_config::{CommitmentConfig, CommitmentLevel};
+use tokio::sync::watch;
+use tokio::time::MissedTickBehavior;
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// a single scrape job that can be run by the daemon, or once from the cli
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Job {
+    InterestRates {
+        #[serde(default)]
+        batched: bool,
+        #[serde(default)]
+        concurrency: Option<usize>,
+        #[serde(default)]
+        lookup_timeout_secs: Option<u64>,
+        /// maximum utilization difference tolerated between two endpoints,
+        /// when set every reserve is read from two endpoints
+        #[serde(default)]
+        quorum_threshold: Option<f64>,
+    },
+    ReserveConfigs,
+    LendingAllocation {
+        #[serde(flatten)]
+        opts: LendingAllocationOpts,
+    },
+    ObligationAccounts {
+        #[serde(default)]
+        compression: bool,
+        #[serde(default)]
+        scan: ScanOpts,
+        /// slices observed by previous scans, carried between runs of the job
+        #[serde(skip)]
+        cache: Arc<Mutex<ScanCache>>,
+    },
+    UserFarms {
+        #[serde(default)]
+        compression: bool,
+        #[serde(default)]
+        scan: ScanOpts,
+        /// slices observed by previous scans, carried between runs of the job
+        #[serde(skip)]
+        cache: Arc<Mutex<ScanCache>>,
+    },
+    ObligationLtvs {
+        #[serde(default)]
+        compression: bool,
+    },
+    LiquidatedPositions {
+        #[serde(default)]
+        compression: bool,
+        /// open liquidations, carried between runs of the job
+        #[serde(skip)]
+        tracker: Arc<Mutex<LiquidationTracker>>,
+    },
+}
+
+/// a job, and how often it should be run
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JobConfig {
+    #[serde(flatten)]
+    pub job: Job,
+    /// number of seconds between the start of consecutive runs
+    pub interval_secs: u64,
+    /// controls how failed rpc requests are retried, the retry
+    /// budget is reset at the start of every run
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// controls when an rpc endpoint is considered healthy
+    #[serde(default)]
+    pub pool: RpcPoolOpts,
+    /// commitment level accounts are read at, stored alongside each sample
+    #[serde(default)]
+    pub commitment: CommitmentLevel,
+}
+
+/// configuration for the scraper daemon, loaded from a yaml file such as
+///
+/// ```yaml
+/// jobs:
+///   - kind: interest_rates
+///     batched: true
+///     quorum_threshold: 0.001
+///     interval_secs: 60
+///     commitment: confirmed
+///     pool:
+///       max_slot_lag: 50
+///       max_error_rate: 0.5
+///       health_check_interval_secs: 30
+///     retry:
+///       max_retries: 4
+///       budget: 20
+///       base_delay_ms: 250
+///       max_delay_ms: 5000
+///       rate_limited_delay_ms: 2000
+///   - kind: reserve_configs
+///     interval_secs: 3600
+///   - kind: lending_allocation
+///     vault_name: LENDING-USDC
+///     asset: USDC
+///     total_deposits: 1000000
+///     platforms: [TULIP, SOLEND, PORT, MANGO]
+///     interval_secs: 300
+///   - kind: user_farms
+///     compression: true
+///     interval_secs: 600
+///     scan:
+///       sliced: true
+///       shard_prefix_len: 1
+///       full_refresh_runs: 24
+///   - kind: obligation_ltvs
+///     compression: true
+///     interval_secs: 300
+///   - kind: liquidated_positions
+///     interval_secs: 30
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct DaemonConfig {
+    pub jobs: Vec<JobConfig>,
+}
+
+/// connection information shared by every job
+#[derive(Clone, Debug)]
+pub struct Endpoints {
+    /// rpc endpoints requests are routed between
+    pub rpc_urls: Vec<String>,
+    /// the database records are written to, or read from when `dry_run` is set
+    pub database_url: Option<String>,
+    /// when true, records are written to stdout instead of the database
+    pub dry_run: bool,
+}
+
+impl DaemonConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let daemon_config: DaemonConfig = serde_yaml::from_slice(&std::fs::read(path)?)?;
+        for job in daemon_config.jobs.iter() {
+            if job.interval_secs == 0 {
+                return Err(anyhow!("job {} has an interval of 0", job.job.name()));
+            }
+            job.job.validate()?;
+        }
+        Ok(daemon_config)
+    }
+}
+
+impl Job {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Job::InterestRates { .. } => "interest_rates",
+            Job::ReserveConfigs => "reserve_configs",
+            Job::LendingAllocation { .. } => "lending_allocation",
+            Job::ObligationAccounts { .. } => "obligation_accounts",
+            Job::UserFarms { .. } => "user_farms",
+            Job::ObligationLtvs { .. } => "obligation_ltvs",
+            Job::LiquidatedPositions { .. } => "liquidated_positions",
+        }
+    }
+    /// returns an error if the job's options are invalid
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Job::ObligationAccounts { scan, .. } | Job::UserFarms { scan, .. } => scan
+                .validate()
+                .map_err(|err| anyhow!("job {} is invalid: {}", self.name(), err)),
+            _ => Ok(()),
+        }
+    }
+    /// runs the job once, returning the report of the scrape
+    pub async fn run(
+        &self,
+        config: &Arc<Configuration>,
+        source: &Arc<dyn AccountSource>,
+        sink: &dyn Sink,
+    ) -> ScrapeReport {
+        match self {
+            Job::InterestRates {
+                batched,
+                concurrency,
+                lookup_timeout_secs,
+                quorum_threshold,
+            } => {
+                let mut opts = InterestRateScrapeOpts {
+                    batched: *batched,
+                    quorum_threshold: *quorum_threshold,
+                    ..Default::default()
+                };
+                if let Some(concurrency) = concurrency {
+                    opts.concurrency = *concurrency;
+                }
+                if let Some(lookup_timeout_secs) = lookup_timeout_secs {
+                    opts.lookup_timeout = Duration::from_secs(*lookup_timeout_secs);
+                }
+                scrape_interest_rates(Arc::clone(config), source, sink, opts).await
+            }
+            Job::ReserveConfigs => scrape_reserve_configs(config, source.as_ref(), sink).await,
+            Job::LendingAllocation { opts } => scrape_lending_allocation(sink, opts),
+            Job::ObligationAccounts {
+                compression,
+                scan,
+                cache,
+            } => {
+                scrape_obligation_accounts(
+                    source.as_ref(),
+                    config,
+                    sink,
+                    *compression,
+                    *scan,
+                    cache,
+                )
+                .await
+            }
+            Job::UserFarms {
+                compression,
+                scan,
+                cache,
+            } => scrape_user_farm(source.as_ref(), config, sink, *compression, *scan, cache).await,
+            Job::ObligationLtvs { compression } => {
+                scrape_obligation_ltvs(source.as_ref(), config, sink, *compression).await
+            }
+            Job::LiquidatedPositions {
+                compression,
+                tracker,
+            } => {
+                scrape_liquidated_positions(source.as_ref(), config, sink, tracker, *compression)
+                    .await
+            }
+        }
+    }
+}
+
+/// returns an account source which routes requests between the given rpc endpoints,
+/// reading accounts at `commitment`, and retrying failed requests according to `policy`
+pub fn rpc_account_source(
+    rpc_urls: &[String],
+    commitment: CommitmentLevel,
+    pool_opts: RpcPoolOpts,
+    policy: RetryPolicy,
+) -> Result<Arc<RetryingAccountSource<RpcPool>>> {
+    Ok(Arc::new(RetryingAccountSource::new(
+        RpcPool::new(rpc_urls, CommitmentConfig { commitment }, pool_opts)?,
+        policy,
+    )))
+}
+
+/// logs every request which exhausted its retries during the last run
+pub fn log_dead_letters<S: AccountSource>(name: &str, source: &RetryingAccountSource<S>) {
+    for dead_letter in source.take_dead_letters() {
+        warn!(
+            "job {} {:?} request {:?} failed after retries: {}",
+            name, dead_letter.class, dead_letter.request, dead_letter.error
+        );
+    }
+}
+
+/// runs every configured job until `shutdown` is set to true, blocking the calling thread.
+///
+/// each job runs on its own thread with its own runtime, database connection and rpc
+/// client, so a slow job never delays another. a job never overlaps with itself, if a
+/// run takes longer than its interval, the missed runs are skipped rather than queued.
+/// once shutdown is requested, in progress runs are allowed to finish before returning
+pub fn run_daemon(
+    daemon_config: DaemonConfig,
+    config: Arc<Configuration>,
+    endpoints: Endpoints,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    if daemon_config.jobs.is_empty() {
+        return Err(anyhow!("no jobs configured"));
+    }
+    let mut handles = Vec::with_capacity(daemon_config.jobs.len());
+    for job_config in daemon_config.jobs.into_iter() {
+        let config = Arc::clone(&config);
+        let endpoints = endpoints.clone();
+        let shutdown = shutdown.clone();
+        handles.push(
+            std::thread::Builder::new()
+                .name(job_config.job.name().to_string())
+                .spawn(move || -> Result<()> {
+                    let runtime = tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()?;
+                    runtime.block_on(job_loop(job_config, config, endpoints, shutdown))
+                })?,
+        );
+    }
+    let mut result = Ok(());
+    for handle in handles {
+        match handle.join() {
+            Ok(Ok(())) => (),
+            Ok(Err(err)) => {
+                error!("job exited with error {:#?}", err);
+                result = Err(err);
+            }
+            Err(_) => {
+                error!("job thread panicked");
+                result = Err(anyhow!("job thread panicked"));
+            }
+        }
+    }
+    result
+}
+
+async fn job_loop(
+    job_config: JobConfig,
+    config: Arc<Configuration>,
+    endpoints: Endpoints,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let name = job_config.job.name();
+    let period = Duration::from_secs(job_config.interval_secs);
+    let conn = match &endpoints.database_url {
+        Some(database_url) => Some(PgConnection::establish(database_url)?),
+        None => None,
+    };
+    let db_sink = conn
+        .as_ref()
+        .map(|conn| DbSink::new(conn, config.analytics.oob_limit));
+    let stdout_sink;
+    let sink: &dyn Sink = match (endpoints.dry_run, &db_sink) {
+        (false, Some(db_sink)) => db_sink,
+        (false, None) => return Err(anyhow!("a database url is required unless dry-running")),
+        (true, Some(db_sink)) => {
+            stdout_sink = StdoutSink::new().with_reader(db_sink);
+            &stdout_sink
+        }
+        (true, None) => {
+            stdout_sink = StdoutSink::new();
+            &stdout_sink
+        }
+    };
+    let retrying_source = rpc_account_source(
+        &endpoints.rpc_urls,
+        job_config.commitment,
+        job_config.pool,
+        job_config.retry,
+    )?;
+    let source: Arc<dyn AccountSource> = retrying_source.clone();
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    info!("starting job {} with interval {:?}", name, period);
+    loop {
+        if *shutdown.borrow() {
+            break;
+        }
+        tokio::select! {
+            _ = interval.tick() => (),
+            changed = shutdown.changed() => {
+                // the sender being dropped is treated as a shutdown request
+                if changed.is_err() {
+                    break;
+                }
+                continue;
+            }
+        }
+        retrying_source.reset_budget();
+        let report = job_config.job.run(&config, &source, sink).await;
+        log_dead_letters(name, &retrying_source);
+        if !report.is_success() {
+            warn!("job {} did not complete successfully: {}", name, report);
+        }
+        if report.elapsed() > period {
+            warn!(
+                "job {} took {:?} which exceeds its interval of {:?}, skipping missed runs",
+                name,
+                report.elapsed(),
+                period
+            );
+        }
+    }
+    info!("job {} stopped", name);
+    Ok(())
+}
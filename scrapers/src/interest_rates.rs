@@ -1,95 +1,379 @@
-use anyhow::Result;
+use crate::metrics;
+use crate::report::{FailureReason, ItemKey, ScrapeReport};
+use crate::sink::{Record, Sink};
 use chrono::Utc;
 
 use config::Configuration;
-use crossbeam::sync::WaitGroup;
 
-use crossbeam_queue::ArrayQueue;
-
-use diesel::PgConnection;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
+use oracle::rate_lookup::account_source::AccountSource;
+use oracle::rate_lookup::lookup::{InterestRateSample, LookupError};
+use oracle::rate_lookup::metrics as lookup_metrics;
+use tokio::sync::Semaphore;
 
 use std::sync::Arc;
+use std::time::Duration;
 
-pub fn scrape_interest_rates(config: Arc<Configuration>, conn: &PgConnection) -> Result<()> {
-    let db_client = Arc::new(db::client::DBClient {
-        conn,
-        oob_limit: config.analytics.oob_limit,
-    });
-    let config = Arc::new(config);
-    let rpc_client = Arc::new(config.get_rpc_client(false, None));
-    let rate_queue = Arc::new(ArrayQueue::new(
-        config.analytics.interest_rates.assets.len(),
-    ));
-    let wg = WaitGroup::new();
-    let rates = config.analytics.interest_rates.assets.clone();
+/// default number of interest rate lookups which may be in flight at once
+pub const DEFAULT_LOOKUP_CONCURRENCY: usize = 8;
+/// default amount of time a single asset/platform lookup may take before being abandoned
+pub const DEFAULT_LOOKUP_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// options which control how interest rate lookups are scheduled
+#[derive(Clone, Copy, Debug)]
+pub struct InterestRateScrapeOpts {
+    /// maximum number of lookups that may be in flight at any given time
+    pub concurrency: usize,
+    /// maximum amount of time to wait for a single asset/platform lookup
+    pub lookup_timeout: Duration,
+    /// when true, the accounts needed by every asset are fetched with a single batched
+    /// lookup, and all samples are computed from the same account snapshot.
+    /// `concurrency` is ignored, and `lookup_timeout` applies to the entire batch
+    pub batched: bool,
+    /// when set, each asset's accounts are read from two independent backends, and
+    /// samples whose utilization rates differ by more than the threshold are not stored.
+    /// this requires an account source with multiple backends, such as `RpcPool`,
+    /// and is ignored in batched mode
+    pub quorum_threshold: Option<f64>,
+}
+
+/// number of backends reserve reads are cross checked against in quorum mode
+pub const QUORUM_SIZE: usize = 2;
+
+/// returned when the samples computed from different backends disagree
+#[derive(Debug, thiserror::Error)]
+#[error("utilization differs by {diff} across {responses} backends, exceeding {threshold}")]
+pub struct QuorumMismatch {
+    pub diff: f64,
+    pub threshold: f64,
+    pub responses: usize,
+}
+
+impl Default for InterestRateScrapeOpts {
+    fn default() -> Self {
+        Self {
+            concurrency: DEFAULT_LOOKUP_CONCURRENCY,
+            lookup_timeout: DEFAULT_LOOKUP_TIMEOUT,
+            batched: false,
+            quorum_threshold: None,
+        }
+    }
+}
+
+/// looks up the interest rates of all configured assets, writing the results to `sink`.
+///
+/// lookups are issued against the given account source, with at most `opts.concurrency`
+/// lookups in flight at once. any lookup which takes longer than `opts.lookup_timeout`
+/// is abandoned so that a single slow reserve doesn't stall the entire run. when
+/// `opts.batched` is set, all assets are instead computed from one account snapshot
+pub async fn scrape_interest_rates(
+    config: Arc<Configuration>,
+    source: &Arc<dyn AccountSource>,
+    sink: &dyn Sink,
+    opts: InterestRateScrapeOpts,
+) -> ScrapeReport {
+    let mut report = ScrapeReport::new("interest_rates");
     let start = Utc::now();
-    for rate in rates.iter() {
-        let rate = rate.clone();
+    let samples = if opts.batched {
+        lookup_interest_rates_batched(&config, source, opts, &mut report).await
+    } else {
+        lookup_interest_rates(&config, source, opts, &mut report).await
+    };
+    report.record_phase("lookup", start);
+    info!("interest rate lookup routines finished, storing results");
+    let store_start = Utc::now();
+    let commitment = source.commitment().commitment.to_string();
+    for record in samples.into_iter() {
+        let key = ItemKey::InterestRate {
+            asset: record.asset.clone(),
+            platform: record.platform.clone(),
+        };
+        if record.projected {
+            debug!(
+                "projected {} to slot {}, reserve was stale for {} slots",
+                key, record.slot, record.stale_slots
+            );
+        }
+        match sink.put(&Record::InterestRate {
+            platform: record.platform.clone(),
+            asset: record.asset.clone(),
+            borrow_rate: record.rate,
+            utilization_rate: record.utilization_rate,
+            lending_rate: record.interest_rate,
+            net_lending_rate: record.net_interest_rate,
+            borrow_apy: record.borrow_apy,
+            lending_apy: record.interest_apy,
+            net_lending_apy: record.net_interest_apy,
+            cumulative_borrow_rate: record.cumulative_borrow_rate,
+            collateral_exchange_rate: record.collateral_exchange_rate,
+            available_amount: record.available_amount,
+            borrowed_amount: record.borrowed_amount,
+            scraped_at: start,
+            slot: record.slot,
+            commitment: commitment.clone(),
+        }) {
+            Ok(outcome) => report.record(key, outcome.into()),
+            Err(err) => {
+                error!(
+                    "failed to update interest rate for asset({}) platform({}): {:#?}",
+                    record.asset, record.platform, err
+                );
+                report.record_failure(key, FailureReason::Database(err.to_string()));
+            }
+        }
+    }
+    report.record_phase("store", store_start);
+    let report = report.finish();
+    metrics::observe_report(&report);
+    info!("{}", report);
+    report
+}
+
+/// looks up the interest rate of each configured asset with its own rpc request,
+/// bounding the number of lookups in flight by `opts.concurrency`
+async fn lookup_interest_rates(
+    config: &Arc<Configuration>,
+    source: &Arc<dyn AccountSource>,
+    opts: InterestRateScrapeOpts,
+    report: &mut ScrapeReport,
+) -> Vec<InterestRateSample> {
+    // a concurrency of 0 would never acquire a permit
+    let semaphore = Arc::new(Semaphore::new(opts.concurrency.max(1)));
+    let rates = config.analytics.interest_rates.assets.clone();
+    let mut lookups = Vec::with_capacity(rates.len());
+    for rate in rates.into_iter() {
         debug!(
             "looking up interest rate for asset({}) platform({})",
             &rate.asset,
             &rate.platform.to_string()
         );
-        let rate_queue = Arc::clone(&rate_queue);
-        let rpc_client = Arc::clone(&rpc_client);
-        let config = Arc::clone(&config);
-        let wg = wg.clone();
-        tokio::task::spawn(async move {
-            match oracle::rate_lookup::lookup::interest_rate(
-                &config,
-                &rpc_client,
-                &rate.asset,
-                &rate.platform.to_string(),
-            ) {
-                Err(err) => {
-                    error!(
-                        "failed to lookup interest rate for {}: {:#?}",
-                        &rate.asset, err
-                    );
-                }
-                Ok(price) => {
-                    if rate_queue.push(price).is_err() {
-                        error!("failed to push price record into queue");
-                    }
-                }
+        let key = ItemKey::InterestRate {
+            asset: rate.asset.clone(),
+            platform: rate.platform.to_string(),
+        };
+        // wait for a free slot before spawning the lookup, which bounds
+        // the number of tasks, and rpc requests in flight
+        let permit = match Arc::clone(&semaphore).acquire_owned().await {
+            Ok(permit) => permit,
+            Err(err) => {
+                report.record_failure(key, FailureReason::Task(err.to_string()));
+                continue;
             }
-            drop(wg);
-        });
+        };
+        let source = Arc::clone(source);
+        let config = Arc::clone(config);
+        lookups.push((
+            key,
+            tokio::task::spawn(async move {
+                let platform = rate.platform.to_string();
+                let lookup = async {
+                    match opts.quorum_threshold {
+                        Some(threshold) => {
+                            quorum_lookup(
+                                &config,
+                                source.as_ref(),
+                                &rate.asset,
+                                &platform,
+                                threshold,
+                            )
+                            .await
+                        }
+                        None => oracle::rate_lookup::lookup::interest_rate_nonblocking(
+                            &config,
+                            source.as_ref(),
+                            &rate.asset,
+                            &platform,
+                        )
+                        .await
+                        .map_err(anyhow::Error::from),
+                    }
+                };
+                let result = tokio::time::timeout(opts.lookup_timeout, lookup).await;
+                drop(permit);
+                result
+            }),
+        ));
     }
     info!("waiting for interest rate lookup routines to finish");
-    wg.wait();
-    info!("interest rate lookup routines finished, storing results");
-    loop {
-        match rate_queue.pop() {
-            Some(record) => {
-                if let Err(err) = db_client.put_interest_rate(
-                    record.platform.clone(),
-                    record.asset.clone(),
-                    record.rate,
-                    record.utilization_rate,
-                    record.interest_rate,
-                    record.available_amount,
-                    record.borrowed_amount,
-                    start,
-                ) {
-                    error!(
-                        "failed to update interest rate for asset({}) platform({}): {:#?}",
-                        record.asset, record.platform, err
+    let mut samples: Vec<InterestRateSample> = Vec::with_capacity(lookups.len());
+    for (key, lookup) in lookups {
+        match lookup.await {
+            Ok(Ok(Ok(sample))) => samples.push(sample),
+            Ok(Ok(Err(err))) => {
+                error!("failed to lookup interest rate for {}: {:#?}", key, err);
+                report.record_failure(key, failure_reason(&err));
+            }
+            Ok(Err(_)) => {
+                warn!(
+                    "interest rate lookup for {} timed out after {:?}",
+                    key, opts.lookup_timeout
+                );
+                if let ItemKey::InterestRate { asset, platform } = &key {
+                    lookup_metrics::observe_lookup(
+                        platform,
+                        asset,
+                        lookup_metrics::OUTCOME_TIMEOUT,
                     );
                 }
+                report.record_failure(key, FailureReason::Timeout(opts.lookup_timeout));
             }
-            None => {
-                info!("no more interest rates to observe");
-                break;
+            Err(err) => {
+                error!("interest rate lookup routine failed {:#?}", err);
+                report.record_failure(key, FailureReason::Task(err.to_string()));
             }
         }
     }
-    let end = Utc::now();
-    let diff = end.signed_duration_since(start);
-    info!(
-        "total time to scrape and update interest rate records {} seconds",
-        diff.num_seconds()
+    samples
+}
+
+/// maps the error returned by a single asset lookup to the reason it failed
+fn failure_reason(err: &anyhow::Error) -> FailureReason {
+    if err.is::<QuorumMismatch>() {
+        return FailureReason::QuorumMismatch(err.to_string());
+    }
+    match err.downcast_ref::<LookupError>() {
+        Some(err) => lookup_failure_reason(err),
+        None => FailureReason::Rpc(err.to_string()),
+    }
+}
+
+/// maps the stage at which a lookup failed to the matching failure reason
+fn lookup_failure_reason(err: &LookupError) -> FailureReason {
+    match err {
+        LookupError::Config(_) => FailureReason::Config(err.to_string()),
+        LookupError::Fetch(_) => FailureReason::Rpc(err.to_string()),
+        LookupError::Decode(_) => FailureReason::Decode(err.to_string()),
+        LookupError::Compute(_) => FailureReason::Compute(err.to_string()),
+    }
+}
+
+/// looks up the interest rate of a single asset, cross checking the
+/// utilization rate across `QUORUM_SIZE` backends
+async fn quorum_lookup(
+    config: &Arc<Configuration>,
+    source: &dyn AccountSource,
+    asset: &str,
+    platform: &str,
+    threshold: f64,
+) -> anyhow::Result<InterestRateSample> {
+    let quorum = oracle::rate_lookup::lookup::interest_rate_quorum(
+        config,
+        source,
+        asset,
+        platform,
+        QUORUM_SIZE,
+    )
+    .await?;
+    if quorum.responses < QUORUM_SIZE {
+        warn!(
+            "quorum for asset({}) platform({}) only received {} responses",
+            asset, platform, quorum.responses
+        );
+    }
+    if quorum.max_utilization_diff > threshold {
+        return Err(QuorumMismatch {
+            diff: quorum.max_utilization_diff,
+            threshold,
+            responses: quorum.responses,
+        }
+        .into());
+    }
+    Ok(quorum.sample)
+}
+
+/// looks up the interest rates of all configured assets from a single batched account fetch
+async fn lookup_interest_rates_batched(
+    config: &Arc<Configuration>,
+    source: &Arc<dyn AccountSource>,
+    opts: InterestRateScrapeOpts,
+    report: &mut ScrapeReport,
+) -> Vec<InterestRateSample> {
+    let (slot, results) = match tokio::time::timeout(
+        opts.lookup_timeout,
+        oracle::rate_lookup::lookup::interest_rates_batched(config, source.as_ref()),
+    )
+    .await
+    {
+        Ok(Ok(results)) => results,
+        Ok(Err(err)) => {
+            error!("batched interest rate lookup failed {:#?}", err);
+            report.set_fatal(FailureReason::Rpc(err.to_string()));
+            return Vec::new();
+        }
+        Err(_) => {
+            warn!(
+                "batched interest rate lookup timed out after {:?}",
+                opts.lookup_timeout
+            );
+            for rate in config.analytics.interest_rates.assets.iter() {
+                lookup_metrics::observe_lookup(
+                    &rate.platform.to_string(),
+                    &rate.asset,
+                    lookup_metrics::OUTCOME_TIMEOUT,
+                );
+            }
+            report.set_fatal(FailureReason::Timeout(opts.lookup_timeout));
+            return Vec::new();
+        }
+    };
+    debug!(
+        "computing interest rates from accounts fetched at slot {}",
+        slot
     );
-    Ok(())
+    let mut samples = Vec::with_capacity(results.len());
+    for (rate, result) in results.into_iter() {
+        match result {
+            Ok(sample) => samples.push(sample),
+            Err(err) => {
+                let key = ItemKey::InterestRate {
+                    asset: rate.asset.clone(),
+                    platform: rate.platform.to_string(),
+                };
+                error!("failed to lookup interest rate for {}: {:#?}", key, err);
+                report.record_failure(key, lookup_failure_reason(&err));
+            }
+        }
+    }
+    samples
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_failure_reason() {
+        let reason = |err: LookupError| failure_reason(&anyhow::Error::from(err));
+        assert_eq!(
+            reason(LookupError::Config(anyhow!("missing"))),
+            FailureReason::Config("missing".to_string())
+        );
+        assert_eq!(
+            reason(LookupError::Fetch(anyhow!("timed out"))),
+            FailureReason::Rpc("timed out".to_string())
+        );
+        assert_eq!(
+            reason(LookupError::Decode(anyhow!("bad data"))),
+            FailureReason::Decode("bad data".to_string())
+        );
+        assert_eq!(
+            reason(LookupError::Compute(anyhow!("overflow"))),
+            FailureReason::Compute("overflow".to_string())
+        );
+        // errors returned by `quorum_lookup` after the lookup itself succeeded
+        let mismatch = anyhow::Error::from(QuorumMismatch {
+            diff: 2.0,
+            threshold: 1.0,
+            responses: 2,
+        });
+        assert!(matches!(
+            failure_reason(&mismatch),
+            FailureReason::QuorumMismatch(_)
+        ));
+        assert_eq!(
+            failure_reason(&anyhow!("unknown")),
+            FailureReason::Rpc("unknown".to_string())
+        );
+    }
 }
@@ -0,0 +1,246 @@
+//! computes how the lending optimizer should split its deposits across lending platforms,
+//! storing the split in the `lending_optimizer_distribution` table
+
+use crate::metrics;
+use crate::report::{FailureReason, ItemKey, ScrapeReport};
+use crate::sink::{Record, Sink};
+use chrono::Utc;
+use db::models::{InterestRate, InterestRateCurve, LendingOptimizerDistribution};
+use log::{error, info};
+use oracle::rate_lookup::allocation::{solve_allocation, LendingMarket, RateCurve};
+use serde::{Deserialize, Serialize};
+
+/// describes the deposits of a single lending optimizer vault
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct LendingAllocationOpts {
+    /// name the distribution is stored under, for example `LENDING-USDC`
+    pub vault_name: String,
+    /// the asset being lent
+    pub asset: String,
+    /// the total amount to split across platforms, in ui units of the asset
+    pub total_deposits: f64,
+    /// platforms the deposits may be split across, when empty every
+    /// platform with an interest rate curve for the asset is used
+    #[serde(default)]
+    pub platforms: Vec<String>,
+}
+
+/// solves for the split of `opts.total_deposits` maximizing the blended supply rate,
+/// using the latest interest rate sample and curve of each platform
+pub fn scrape_lending_allocation(sink: &dyn Sink, opts: &LendingAllocationOpts) -> ScrapeReport {
+    let mut report = ScrapeReport::new("lending_allocation");
+    let key = ItemKey::Vault(opts.vault_name.clone());
+    let load_start = Utc::now();
+    let markets = sink.lending_markets(&opts.asset).and_then(|markets| {
+        let distribution = sink.lending_optimizer_distribution(&opts.vault_name)?;
+        Ok(lending_markets(
+            markets,
+            distribution.as_ref(),
+            &opts.platforms,
+        ))
+    });
+    report.record_phase("load", load_start);
+    let markets = match markets {
+        Ok(markets) if markets.is_empty() => {
+            error!("found no lending markets for {}", opts.asset);
+            report.record_failure(
+                key,
+                FailureReason::Unresolved(format!("no lending markets for {}", opts.asset)),
+            );
+            return finish_report(report);
+        }
+        Ok(markets) => markets,
+        Err(err) => {
+            error!("failed to load lending markets {:#?}", err);
+            report.set_fatal(FailureReason::Database(err.to_string()));
+            return finish_report(report);
+        }
+    };
+    let solve_start = Utc::now();
+    let plan = solve_allocation(&markets, opts.total_deposits);
+    report.record_phase("solve", solve_start);
+    let plan = match plan {
+        Ok(plan) => plan,
+        Err(err) => {
+            error!("failed to solve allocation for {}: {:#?}", key, err);
+            report.record_failure(key, FailureReason::Compute(err.to_string()));
+            return finish_report(report);
+        }
+    };
+    for allocation in plan.allocations.iter() {
+        info!(
+            "allocating {} {} to {} at a supply rate of {}",
+            allocation.amount, opts.asset, allocation.platform, allocation.supply_rate
+        );
+    }
+    info!("blended supply rate of {}", plan.blended_rate);
+    let store_start = Utc::now();
+    match sink.put(&Record::LendingOptimizerDistribution {
+        vault_name: opts.vault_name.clone(),
+        platforms: plan
+            .allocations
+            .iter()
+            .map(|allocation| allocation.platform.clone())
+            .collect(),
+        deposited_balances: plan
+            .allocations
+            .iter()
+            .map(|allocation| allocation.amount)
+            .collect(),
+    }) {
+        Ok(outcome) => report.record(key, outcome.into()),
+        Err(err) => {
+            error!("failed to put distribution for {}: {:#?}", key, err);
+            report.record_failure(key, FailureReason::Database(err.to_string()));
+        }
+    }
+    report.record_phase("store", store_start);
+    finish_report(report)
+}
+
+/// converts the stored curves and samples into lending markets sorted by platform,
+/// using the previous distribution to determine how much of each platform's
+/// available liquidity was deposited by the vault itself
+pub fn lending_markets(
+    markets: Vec<(InterestRateCurve, InterestRate)>,
+    distribution: Option<&LendingOptimizerDistribution>,
+    platforms: &[String],
+) -> Vec<LendingMarket> {
+    let mut markets = markets
+        .into_iter()
+        .filter(|(curve, _)| {
+            platforms.is_empty()
+                || platforms
+                    .iter()
+                    .any(|platform| platform.eq_ignore_ascii_case(&curve.platform))
+        })
+        .map(|(curve, rate)| {
+            let own_deposit = distribution
+                .and_then(|distribution| {
+                    distribution
+                        .standalone_vault_platforms
+                        .iter()
+                        .position(|platform| platform.eq_ignore_ascii_case(&curve.platform))
+                        .and_then(|idx| distribution.standalone_vault_deposited_balances.get(idx))
+                })
+                .copied()
+                .unwrap_or_default();
+            LendingMarket {
+                platform: curve.platform.clone(),
+                available_amount: rate.available_amount,
+                borrowed_amount: rate.borrowed_amount,
+                own_deposit,
+                curve: RateCurve::from_percentages(
+                    curve.min_borrow_rate,
+                    curve.max_borrow_rate,
+                    curve.optimal_borrow_rate,
+                    curve.optimal_utilization_rate,
+                    curve.degen_borrow_rate,
+                    curve.degen_utilization_rate,
+                ),
+                take_rate: take_rate(&rate),
+            }
+        })
+        .collect::<Vec<_>>();
+    markets.sort_by(|a, b| a.platform.cmp(&b.platform));
+    markets
+}
+
+/// derives the protocol take rate from the gross and net lending rates of a sample
+fn take_rate(rate: &InterestRate) -> f64 {
+    if rate.lending_rate <= 0_f64 {
+        return 0_f64;
+    }
+    (1_f64 - rate.net_lending_rate / rate.lending_rate)
+        .max(0_f64)
+        .min(1_f64)
+}
+
+fn finish_report(report: ScrapeReport) -> ScrapeReport {
+    let report = report.finish();
+    metrics::observe_report(&report);
+    info!("{}", report);
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn market(platform: &str, available_amount: f64) -> (InterestRateCurve, InterestRate) {
+        (
+            InterestRateCurve {
+                platform: platform.to_string(),
+                asset: "USDC".to_string(),
+                optimal_borrow_rate: 10_f64,
+                optimal_utilization_rate: 80_f64,
+                max_borrow_rate: 150_f64,
+                ..Default::default()
+            },
+            InterestRate {
+                platform: platform.to_string(),
+                asset: "USDC".to_string(),
+                available_amount,
+                borrowed_amount: 100_f64,
+                lending_rate: 10_f64,
+                net_lending_rate: 8_f64,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_lending_markets() {
+        let distribution = LendingOptimizerDistribution {
+            vault_name: "LENDING-USDC".to_string(),
+            standalone_vault_platforms: vec!["tulip".to_string(), "solend".to_string()],
+            standalone_vault_deposited_balances: vec![5_f64, 7_f64],
+            ..Default::default()
+        };
+        let markets = lending_markets(
+            vec![
+                market("TULIP", 10_f64),
+                market("SOLEND", 20_f64),
+                market("PORT", 30_f64),
+            ],
+            Some(&distribution),
+            &[],
+        );
+        let platforms = markets
+            .iter()
+            .map(|m| m.platform.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(platforms, vec!["PORT", "SOLEND", "TULIP"]);
+        assert_eq!(markets[0].own_deposit, 0_f64);
+        assert_eq!(markets[1].own_deposit, 7_f64);
+        assert_eq!(markets[2].own_deposit, 5_f64);
+        assert_eq!(markets[2].available_amount, 10_f64);
+        assert!((markets[2].curve.optimal_borrow_rate - 0.1).abs() < 1e-12);
+        assert!((markets[2].take_rate - 0.2).abs() < 1e-12);
+
+        let markets = lending_markets(
+            vec![market("TULIP", 10_f64), market("SOLEND", 20_f64)],
+            None,
+            &["solend".to_string()],
+        );
+        assert_eq!(markets.len(), 1);
+        assert_eq!(markets[0].platform, "SOLEND");
+    }
+
+    #[test]
+    fn test_mango_allocation() {
+        // mango curves are stored from the root bank, without a degen kink. at
+        // 83% utilization mango pays more than solend, so should receive deposits
+        let (mut curve, rate) = market("MANGO", 20_f64);
+        curve.optimal_utilization_rate = 70_f64;
+        curve.optimal_borrow_rate = 6_f64;
+        curve.max_borrow_rate = 150_f64;
+        let markets = lending_markets(vec![(curve, rate), market("SOLEND", 100_f64)], None, &[]);
+        assert_eq!(markets[0].platform, "MANGO");
+        let plan = solve_allocation(&markets, 100_f64).unwrap();
+        assert_eq!(plan.allocations[0].platform, "MANGO");
+        assert!(plan.allocations[0].amount > 25_f64);
+        let allocated: f64 = plan.allocations.iter().map(|a| a.amount).sum();
+        assert!((allocated - 100_f64).abs() < 1e-6);
+    }
+}
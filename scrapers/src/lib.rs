@@ -5,8 +5,15 @@
 #![allow(clippy::too_many_arguments)]
 #![allow(clippy::extra_unused_lifetimes)]
 
+pub mod daemon;
 pub mod deposit_tracking;
 pub mod interest_rates;
+pub mod lending_optimizer;
+pub mod metrics;
+pub mod report;
+pub mod reserve_configs;
+pub mod scan;
+pub mod sink;
 pub mod staking_metrics;
 pub mod token_balances;
 pub mod token_prices;
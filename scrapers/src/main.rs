@@ -0,0 +1,270 @@
+use anyhow::{anyhow, Result};
+use clap::{Args, Parser, Subcommand};
+use config::Configuration;
+use diesel::{Connection, PgConnection};
+use log::{error, info, LevelFilter};
+use oracle::rate_lookup::account_source::AccountSource;
+use oracle::rate_lookup::retry::RetryPolicy;
+use oracle::rate_lookup::rpc_pool::RpcPoolOpts;
+use scrapers::daemon::{
+    log_dead_letters, rpc_account_source, run_daemon, DaemonConfig, Endpoints, Job,
+};
+use scrapers::lending_optimizer::LendingAllocationOpts;
+use scrapers::metrics;
+use scrapers::scan::ScanOpts;
+use scrapers::sink::{DbSink, StdoutSink};
+use simplelog::{ColorChoice, TermLogger, TerminalMode};
+use solana_sdk::commitment_config::CommitmentLevel;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Parser, Debug)]
+#[clap(name = "scrapers", about = "tulip analytics scrapers")]
+struct Cli {
+    /// path to the yaml configuration file
+    #[clap(long, short, default_value = "config.yaml")]
+    config: PathBuf,
+    /// comma separated list of rpc endpoints, requests are routed to healthy endpoints
+    #[clap(long, env = "RPC_URL", use_value_delimiter = true, required = true)]
+    rpc_url: Vec<String>,
+    /// in dry-run mode the database is optional, and only read from by jobs
+    /// which depend on previously stored records
+    #[clap(long, env = "DATABASE_URL", required_unless_present = "dry_run")]
+    database_url: Option<String>,
+    /// run the full scrape without writing to the database, writing the
+    /// records that would have been stored to stdout as json lines
+    #[clap(long)]
+    dry_run: bool,
+    #[clap(long, default_value = "info")]
+    log_level: LevelFilter,
+    /// commitment level accounts are read at when running a single job,
+    /// daemon jobs configure their commitment in the daemon configuration
+    #[clap(long, default_value = "finalized")]
+    commitment: CommitmentLevel,
+    /// address to serve prometheus metrics on, for example 127.0.0.1:9184
+    #[clap(long)]
+    metrics_addr: Option<SocketAddr>,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// scrape interest rates for all configured assets
+    InterestRates {
+        /// compute every asset from a single batched account fetch
+        #[clap(long)]
+        batched: bool,
+        /// maximum number of lookups in flight at once
+        #[clap(long)]
+        concurrency: Option<usize>,
+        /// maximum number of seconds a single lookup may take
+        #[clap(long)]
+        lookup_timeout_secs: Option<u64>,
+        /// read every reserve from two rpc endpoints, failing samples whose
+        /// utilization rates differ by more than this threshold
+        #[clap(long)]
+        quorum_threshold: Option<f64>,
+    },
+    /// scrape the interest rate curve of every configured lending reserve
+    ReserveConfigs,
+    /// solve for the split of a lending optimizer vault's deposits across platforms
+    /// which maximizes the blended supply rate
+    LendingAllocation {
+        /// name the distribution is stored under, for example LENDING-USDC
+        #[clap(long)]
+        vault_name: String,
+        #[clap(long)]
+        asset: String,
+        /// the total amount to split across platforms, in ui units of the asset
+        #[clap(long)]
+        total_deposits: f64,
+        /// comma separated platforms to split deposits across, defaults to all
+        #[clap(long, use_value_delimiter = true)]
+        platforms: Vec<String>,
+    },
+    /// store previously unseen v1 obligation accounts
+    ObligationAccounts {
+        #[clap(long)]
+        compression: bool,
+        #[clap(flatten)]
+        scan: ScanArgs,
+    },
+    /// scrape v1 user farm accounts
+    UserFarms {
+        #[clap(long)]
+        compression: bool,
+        #[clap(flatten)]
+        scan: ScanArgs,
+    },
+    /// compute the ltv of every v1 obligation, resolving owners from stored user farms
+    ObligationLtvs {
+        #[clap(long)]
+        compression: bool,
+    },
+    /// open and close v1 liquidated positions by comparing the temp liquidation
+    /// accounts against the positions previously stored
+    LiquidatedPositions {
+        #[clap(long)]
+        compression: bool,
+    },
+    /// run jobs on the intervals given in the daemon configuration file until SIGTERM
+    Daemon {
+        /// path to the yaml daemon configuration file
+        #[clap(long)]
+        jobs: PathBuf,
+    },
+}
+
+/// controls how v1 program accounts are scanned
+#[derive(Args, Debug)]
+struct ScanArgs {
+    /// only process accounts which are new, or changed since the
+    /// last scan. as a single run has no previous scan, this is mostly useful
+    /// for daemon jobs
+    #[clap(long)]
+    sliced: bool,
+    /// split the scan into 256^n sequential requests using the leading n bytes of the
+    /// owner key, each read at its own slot. n is at most 2
+    #[clap(long, default_value = "0")]
+    shard_prefix_len: usize,
+}
+
+impl From<ScanArgs> for ScanOpts {
+    fn from(args: ScanArgs) -> Self {
+        Self {
+            sliced: args.sliced,
+            shard_prefix_len: args.shard_prefix_len,
+            full_refresh_runs: 0,
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    // dry-run records are written to stdout, so keep logs out of the way
+    let terminal_mode = if cli.dry_run {
+        TerminalMode::Stderr
+    } else {
+        TerminalMode::Mixed
+    };
+    TermLogger::init(
+        cli.log_level,
+        simplelog::Config::default(),
+        terminal_mode,
+        ColorChoice::Auto,
+    )?;
+    let config: Configuration = serde_yaml::from_slice(&std::fs::read(&cli.config)?)?;
+    let config = Arc::new(config);
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    if let Some(metrics_addr) = cli.metrics_addr {
+        let shutdown_rx = shutdown_rx.clone();
+        tokio::task::spawn(async move {
+            if let Err(err) = metrics::serve(metrics_addr, shutdown_rx).await {
+                error!("metrics server failed {:#?}", err);
+            }
+        });
+    }
+    let job = match cli.command {
+        Command::InterestRates {
+            batched,
+            concurrency,
+            lookup_timeout_secs,
+            quorum_threshold,
+        } => Job::InterestRates {
+            batched,
+            concurrency,
+            lookup_timeout_secs,
+            quorum_threshold,
+        },
+        Command::ReserveConfigs => Job::ReserveConfigs,
+        Command::LendingAllocation {
+            vault_name,
+            asset,
+            total_deposits,
+            platforms,
+        } => Job::LendingAllocation {
+            opts: LendingAllocationOpts {
+                vault_name,
+                asset,
+                total_deposits,
+                platforms,
+            },
+        },
+        Command::ObligationAccounts { compression, scan } => Job::ObligationAccounts {
+            compression,
+            scan: scan.into(),
+            cache: Default::default(),
+        },
+        Command::UserFarms { compression, scan } => Job::UserFarms {
+            compression,
+            scan: scan.into(),
+            cache: Default::default(),
+        },
+        Command::ObligationLtvs { compression } => Job::ObligationLtvs { compression },
+        Command::LiquidatedPositions { compression } => Job::LiquidatedPositions {
+            compression,
+            tracker: Default::default(),
+        },
+        Command::Daemon { jobs } => {
+            let daemon_config = DaemonConfig::load(jobs)?;
+            let endpoints = Endpoints {
+                rpc_urls: cli.rpc_url,
+                database_url: cli.database_url,
+                dry_run: cli.dry_run,
+            };
+            let mut sigterm = signal(SignalKind::terminate())?;
+            tokio::task::spawn(async move {
+                tokio::select! {
+                    _ = sigterm.recv() => info!("received SIGTERM, shutting down"),
+                    _ = tokio::signal::ctrl_c() => info!("received SIGINT, shutting down"),
+                }
+                let _ = shutdown_tx.send(true);
+            });
+            return tokio::task::spawn_blocking(move || {
+                run_daemon(daemon_config, config, endpoints, shutdown_rx)
+            })
+            .await?;
+        }
+    };
+    job.validate()?;
+    let retrying_source = rpc_account_source(
+        &cli.rpc_url,
+        cli.commitment,
+        RpcPoolOpts::default(),
+        RetryPolicy::default(),
+    )?;
+    let source: Arc<dyn AccountSource> = retrying_source.clone();
+    let report = match (cli.dry_run, &cli.database_url) {
+        (false, Some(database_url)) => {
+            let conn = PgConnection::establish(database_url)?;
+            let sink = DbSink::new(&conn, config.analytics.oob_limit);
+            job.run(&config, &source, &sink).await
+        }
+        (false, None) => {
+            return Err(anyhow!(
+                "--database-url is required unless --dry-run is set"
+            ))
+        }
+        (true, Some(database_url)) => {
+            let conn = PgConnection::establish(database_url)?;
+            let reader = DbSink::new(&conn, config.analytics.oob_limit);
+            job.run(&config, &source, &StdoutSink::new().with_reader(&reader))
+                .await
+        }
+        (true, None) => job.run(&config, &source, &StdoutSink::new()).await,
+    };
+    log_dead_letters(job.name(), &retrying_source);
+    if let Some(fatal) = &report.fatal {
+        return Err(anyhow!("{} failed: {}", report.scraper, fatal));
+    }
+    for (key, reason) in report.failures() {
+        error!("{} failed: {}", key, reason);
+    }
+    Ok(())
+}
@@ -0,0 +1,147 @@
+//! prometheus metrics for scrapers, and a minimal http endpoint which exposes
+//! every metric in the default registry, including those recorded by the oracle
+
+use crate::report::{ItemOutcome, ScrapeReport};
+use anyhow::Result;
+use log::{error, info};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, Encoder,
+    HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+
+use std::net::SocketAddr;
+use std::time::Instant;
+
+/// number of items processed by scrapers, labelled by scraper and outcome
+pub static SCRAPE_ITEMS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "scraper_items_total",
+        "number of items processed by scraper and outcome",
+        &["scraper", "outcome"]
+    )
+    .unwrap()
+});
+
+/// latency of database writes in seconds, labelled by table
+pub static DB_WRITE_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "scraper_db_write_latency_seconds",
+        "latency of database writes by table",
+        &["table"]
+    )
+    .unwrap()
+});
+
+/// unix timestamp of the last scrape which completed without any failures
+pub static LAST_SUCCESS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "scraper_last_success_timestamp_seconds",
+        "unix timestamp of the last successful scrape by scraper",
+        &["scraper"]
+    )
+    .unwrap()
+});
+
+/// records the latency of a database write which began at `start`
+pub fn observe_db_write(table: &str, start: Instant) {
+    DB_WRITE_LATENCY
+        .with_label_values(&[table])
+        .observe(start.elapsed().as_secs_f64());
+}
+
+/// records the item outcomes of a finished scrape, updating the
+/// last success timestamp if the scrape had no failures
+pub fn observe_report(report: &ScrapeReport) {
+    for item in report.items.iter() {
+        let outcome = match &item.outcome {
+            ItemOutcome::Inserted => "inserted",
+            ItemOutcome::Updated => "updated",
+            ItemOutcome::Skipped => "skipped",
+            ItemOutcome::Failed(_) => "failed",
+        };
+        SCRAPE_ITEMS
+            .with_label_values(&[report.scraper, outcome])
+            .inc();
+    }
+    if report.is_success() {
+        LAST_SUCCESS
+            .with_label_values(&[report.scraper])
+            .set(report.finished_at.timestamp());
+    }
+}
+
+/// serves the default prometheus registry at `GET /metrics` until `shutdown` is set
+pub async fn serve(addr: SocketAddr, mut shutdown: watch::Receiver<bool>) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("serving metrics on {}", addr);
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    tokio::task::spawn(async move {
+                        if let Err(err) = handle_request(stream).await {
+                            error!("failed to handle metrics request {:#?}", err);
+                        }
+                    });
+                }
+                Err(err) => error!("failed to accept metrics connection {:#?}", err),
+            },
+            _ = shutdown.changed() => break,
+        }
+    }
+    Ok(())
+}
+
+async fn handle_request(mut stream: TcpStream) -> Result<()> {
+    // the request line is all that is needed, so a single read is sufficient
+    let mut buffer = [0_u8; 1024];
+    let read = stream.read(&mut buffer).await?;
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let response = if request.starts_with("GET /metrics ") {
+        let encoder = TextEncoder::new();
+        let mut body = Vec::new();
+        encoder.encode(&prometheus::gather(), &mut body)?;
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            encoder.format_type(),
+            body.len()
+        )
+        .into_bytes();
+        response.extend_from_slice(&body);
+        response
+    } else {
+        b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec()
+    };
+    stream.write_all(&response).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::ItemKey;
+
+    #[test]
+    fn test_observe_report() {
+        let mut report = ScrapeReport::new("metrics_test");
+        report.record(ItemKey::Account("a".to_string()), ItemOutcome::Inserted);
+        report.record(ItemKey::Account("b".to_string()), ItemOutcome::Inserted);
+        let report = report.finish();
+        observe_report(&report);
+        assert_eq!(
+            SCRAPE_ITEMS
+                .with_label_values(&["metrics_test", "inserted"])
+                .get(),
+            2
+        );
+        assert_eq!(
+            LAST_SUCCESS.with_label_values(&["metrics_test"]).get(),
+            report.finished_at.timestamp()
+        );
+    }
+}
@@ -0,0 +1,252 @@
+//! structured reporting of scraper runs, allowing callers to determine
+//! what succeeded, what failed, and why, without needing to parse log output
+use chrono::prelude::*;
+use db::client::PutOutcome;
+use std::fmt;
+use std::time::Duration;
+
+/// identifies a single item processed by a scraper
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ItemKey {
+    /// an interest rate sample for an asset on a lending platform
+    InterestRate { asset: String, platform: String },
+    /// an on-chain account, identified by its address
+    Account(String),
+    /// a vault, identified by its name
+    Vault(String),
+}
+
+/// the reason an item, or an entire scrape, failed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FailureReason {
+    /// the item is missing from, or invalid in, the configuration
+    Config(String),
+    /// an rpc request failed
+    Rpc(String),
+    /// the lookup for the item did not complete within the allotted time
+    Timeout(Duration),
+    /// account data could not be deserialized
+    Decode(String),
+    /// account data was decoded, but values could not be computed from it
+    Compute(String),
+    /// the item could not be written to the database
+    Database(String),
+    /// the task processing the item panicked, or was cancelled
+    Task(String),
+    /// reads of the same accounts from different rpc backends disagreed
+    QuorumMismatch(String),
+    /// the item references a record which has not been scraped yet
+    Unresolved(String),
+}
+
+/// the outcome of processing a single item
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ItemOutcome {
+    /// a new row was written to the database
+    Inserted,
+    /// an existing row was updated
+    Updated,
+    /// the item was processed but nothing needed to be written
+    Skipped,
+    /// the item could not be processed
+    Failed(FailureReason),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ItemReport {
+    pub key: ItemKey,
+    pub outcome: ItemOutcome,
+}
+
+/// the amount of time spent in a single phase of a scrape, such as
+/// fetching accounts from rpc, or storing results in the database
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PhaseTiming {
+    pub phase: &'static str,
+    pub duration: Duration,
+}
+
+/// the result of a single scraper run
+#[derive(Clone, Debug)]
+pub struct ScrapeReport {
+    /// name of the scraper that produced the report
+    pub scraper: &'static str,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    /// outcome of every item the scraper attempted to process
+    pub items: Vec<ItemReport>,
+    /// timing information for each phase of the scrape, in the order they ran
+    pub phases: Vec<PhaseTiming>,
+    /// set when the scrape failed before any items could be processed,
+    /// for example when the initial getProgramAccounts request fails
+    pub fatal: Option<FailureReason>,
+}
+
+impl ScrapeReport {
+    pub fn new(scraper: &'static str) -> Self {
+        let now = Utc::now();
+        Self {
+            scraper,
+            started_at: now,
+            finished_at: now,
+            items: Vec::new(),
+            phases: Vec::new(),
+            fatal: None,
+        }
+    }
+    /// records the outcome of processing a single item
+    pub fn record(&mut self, key: ItemKey, outcome: ItemOutcome) {
+        self.items.push(ItemReport { key, outcome });
+    }
+    /// records a failure to process a single item
+    pub fn record_failure(&mut self, key: ItemKey, reason: FailureReason) {
+        self.record(key, ItemOutcome::Failed(reason));
+    }
+    /// records a phase which began at `phase_start` and ended now
+    pub fn record_phase(&mut self, phase: &'static str, phase_start: DateTime<Utc>) {
+        let duration = Utc::now()
+            .signed_duration_since(phase_start)
+            .to_std()
+            .unwrap_or_default();
+        self.phases.push(PhaseTiming { phase, duration });
+    }
+    /// marks the scrape as having failed entirely
+    pub fn set_fatal(&mut self, reason: FailureReason) {
+        self.fatal = Some(reason);
+    }
+    /// marks the scrape as finished, returning the completed report
+    pub fn finish(mut self) -> Self {
+        self.finished_at = Utc::now();
+        self
+    }
+    pub fn inserted(&self) -> usize {
+        self.count(|outcome| matches!(outcome, ItemOutcome::Inserted))
+    }
+    pub fn updated(&self) -> usize {
+        self.count(|outcome| matches!(outcome, ItemOutcome::Updated))
+    }
+    pub fn skipped(&self) -> usize {
+        self.count(|outcome| matches!(outcome, ItemOutcome::Skipped))
+    }
+    pub fn failed(&self) -> usize {
+        self.count(|outcome| matches!(outcome, ItemOutcome::Failed(_)))
+    }
+    /// returns all items which failed to be processed
+    pub fn failures(&self) -> impl Iterator<Item = (&ItemKey, &FailureReason)> {
+        self.items.iter().filter_map(|item| match &item.outcome {
+            ItemOutcome::Failed(reason) => Some((&item.key, reason)),
+            _ => None,
+        })
+    }
+    /// returns true if the scrape ran to completion without any item failures
+    pub fn is_success(&self) -> bool {
+        self.fatal.is_none() && self.failed() == 0
+    }
+    /// total wall clock time of the scrape
+    pub fn elapsed(&self) -> Duration {
+        self.finished_at
+            .signed_duration_since(self.started_at)
+            .to_std()
+            .unwrap_or_default()
+    }
+    fn count(&self, filter: impl Fn(&ItemOutcome) -> bool) -> usize {
+        self.items
+            .iter()
+            .filter(|item| filter(&item.outcome))
+            .count()
+    }
+}
+
+impl From<PutOutcome> for ItemOutcome {
+    fn from(outcome: PutOutcome) -> Self {
+        match outcome {
+            PutOutcome::Inserted => ItemOutcome::Inserted,
+            PutOutcome::Updated => ItemOutcome::Updated,
+            PutOutcome::Skipped => ItemOutcome::Skipped,
+        }
+    }
+}
+
+impl fmt::Display for ItemKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ItemKey::InterestRate { asset, platform } => {
+                write!(f, "asset({}) platform({})", asset, platform)
+            }
+            ItemKey::Account(account) => write!(f, "account({})", account),
+            ItemKey::Vault(vault) => write!(f, "vault({})", vault),
+        }
+    }
+}
+
+impl fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureReason::Config(msg) => write!(f, "config error: {}", msg),
+            FailureReason::Rpc(msg) => write!(f, "rpc error: {}", msg),
+            FailureReason::Timeout(after) => write!(f, "timed out after {:?}", after),
+            FailureReason::Decode(msg) => write!(f, "decode error: {}", msg),
+            FailureReason::Compute(msg) => write!(f, "compute error: {}", msg),
+            FailureReason::Database(msg) => write!(f, "database error: {}", msg),
+            FailureReason::Task(msg) => write!(f, "task error: {}", msg),
+            FailureReason::QuorumMismatch(msg) => write!(f, "quorum mismatch: {}", msg),
+            FailureReason::Unresolved(msg) => write!(f, "unresolved: {}", msg),
+        }
+    }
+}
+
+impl fmt::Display for ScrapeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} finished in {:?}: inserted {}, updated {}, skipped {}, failed {}",
+            self.scraper,
+            self.elapsed(),
+            self.inserted(),
+            self.updated(),
+            self.skipped(),
+            self.failed()
+        )?;
+        for phase in self.phases.iter() {
+            write!(f, ", {} {:?}", phase.phase, phase.duration)?;
+        }
+        if let Some(fatal) = &self.fatal {
+            write!(f, ", fatal {}", fatal)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scrape_report_counts() {
+        let mut report = ScrapeReport::new("test");
+        report.record(ItemKey::Account("a".to_string()), ItemOutcome::Inserted);
+        report.record(ItemKey::Account("b".to_string()), ItemOutcome::Updated);
+        report.record(ItemKey::Account("c".to_string()), ItemOutcome::Updated);
+        report.record(ItemKey::Account("d".to_string()), ItemOutcome::Skipped);
+        assert!(report.is_success());
+        report.record_failure(
+            ItemKey::InterestRate {
+                asset: "USDC".to_string(),
+                platform: "TULIP".to_string(),
+            },
+            FailureReason::Timeout(Duration::from_secs(1)),
+        );
+        let report = report.finish();
+        assert_eq!(report.inserted(), 1);
+        assert_eq!(report.updated(), 2);
+        assert_eq!(report.skipped(), 1);
+        assert_eq!(report.failed(), 1);
+        assert!(!report.is_success());
+        let failures = report.failures().collect::<Vec<_>>();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(
+            failures[0].1,
+            &FailureReason::Timeout(Duration::from_secs(1))
+        );
+    }
+}
@@ -0,0 +1,246 @@
+//! scrapes the interest rate curve parameters from the `ReserveConfig` of each configured
+//! lending reserve, or the `RootBank` of each mango asset, keeping the `interest_rate_curve`
+//! table in sync with on-chain values
+
+use crate::metrics;
+use crate::report::{FailureReason, ItemKey, ScrapeReport};
+use crate::sink::{Record, Sink};
+use anchor_client::solana_sdk::account::Account;
+use anchor_client::solana_sdk::program_pack::Pack;
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use common::mango::mango_lib::state::RootBank;
+use common::port::port_variable_rate_lending_instructions::state as port_state;
+use common::solend::solend_token_lending::state as solend_state;
+use common::tulip as tulip_state;
+use config::analytics::Platform;
+use config::Configuration;
+use log::{debug, error, info};
+use oracle::rate_lookup::account_source::AccountSource;
+use oracle::rate_lookup::lookup::fetch_account_snapshot;
+
+use std::sync::Arc;
+
+/// the parameters of a reserve's interest rate curve, as percentages
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReserveCurve {
+    pub min_borrow_rate: f64,
+    pub max_borrow_rate: f64,
+    pub optimal_borrow_rate: f64,
+    pub optimal_utilization_rate: f64,
+    /// only used by tulip, 0 for platforms without a degen utilization kink
+    pub degen_borrow_rate: f64,
+    /// only used by tulip, 0 for platforms without a degen utilization kink
+    pub degen_utilization_rate: f64,
+}
+
+/// decodes the interest rate curve from the reserve account of the given platform,
+/// which for mango is the root bank of the asset
+pub fn reserve_curve(platform: &Platform, reserve: &Account) -> Result<ReserveCurve> {
+    match platform {
+        Platform::Tulip => {
+            let config =
+                tulip_state::lending_reserve::Reserve::unpack_unchecked(&reserve.data[..])?.config;
+            Ok(ReserveCurve {
+                min_borrow_rate: config.min_borrow_rate as f64,
+                max_borrow_rate: config.max_borrow_rate as f64,
+                optimal_borrow_rate: config.optimal_borrow_rate as f64,
+                optimal_utilization_rate: config.optimal_utilization_rate as f64,
+                degen_borrow_rate: config.degen_borrow_rate as f64,
+                degen_utilization_rate: config.degen_utilization_rate as f64,
+            })
+        }
+        Platform::Solend => {
+            let config = solend_state::Reserve::unpack_unchecked(&reserve.data[..])?.config;
+            Ok(ReserveCurve {
+                min_borrow_rate: config.min_borrow_rate as f64,
+                max_borrow_rate: config.max_borrow_rate as f64,
+                optimal_borrow_rate: config.optimal_borrow_rate as f64,
+                optimal_utilization_rate: config.optimal_utilization_rate as f64,
+                ..Default::default()
+            })
+        }
+        Platform::Port => {
+            let config = port_state::Reserve::unpack_unchecked(&reserve.data[..])?.config;
+            Ok(ReserveCurve {
+                min_borrow_rate: config.min_borrow_rate as f64,
+                max_borrow_rate: config.max_borrow_rate as f64,
+                optimal_borrow_rate: config.optimal_borrow_rate as f64,
+                optimal_utilization_rate: config.optimal_utilization_rate as f64,
+                ..Default::default()
+            })
+        }
+        Platform::MangoV3 => {
+            // mango rates rise linearly from 0 to the optimal rate, and from there to
+            // the max rate at full utilization, without a degen kink
+            if reserve.data.len() != std::mem::size_of::<RootBank>() {
+                return Err(anyhow!("invalid root bank size {}", reserve.data.len()));
+            }
+            let root_bank = RootBank::load_from_bytes(&reserve.data[..])?;
+            Ok(ReserveCurve {
+                min_borrow_rate: 0_f64,
+                max_borrow_rate: root_bank.max_rate.to_num::<f64>() * 100_f64,
+                optimal_borrow_rate: root_bank.optimal_rate.to_num::<f64>() * 100_f64,
+                optimal_utilization_rate: root_bank.optimal_util.to_num::<f64>() * 100_f64,
+                ..Default::default()
+            })
+        }
+        _ => Err(anyhow!("platform {} has no reserve config", platform)),
+    }
+}
+
+/// reads the reserve config of every configured lending reserve, upserting the
+/// interest rate curve of each reserve whose parameters changed
+pub async fn scrape_reserve_configs(
+    config: &Arc<Configuration>,
+    source: &dyn AccountSource,
+    sink: &dyn Sink,
+) -> ScrapeReport {
+    let mut report = ScrapeReport::new("reserve_configs");
+    // mango doesn't use spl lending style reserves, so its curve is read from the root bank
+    let reserves = config
+        .analytics
+        .interest_rates
+        .assets
+        .iter()
+        .filter_map(|rate| {
+            if let Some(spl_config) = rate.spl_lending_config.as_ref() {
+                return Some((rate, spl_config.reserve()));
+            }
+            rate.mango_config
+                .as_ref()
+                .map(|mango_config| (rate, mango_config.root_bank()))
+        })
+        .collect::<Vec<_>>();
+    let mut account_keys: Vec<Pubkey> = Vec::with_capacity(reserves.len());
+    for (_, reserve) in reserves.iter() {
+        if !account_keys.contains(reserve) {
+            account_keys.push(*reserve);
+        }
+    }
+    let fetch_start = Utc::now();
+    let snapshot = fetch_account_snapshot(source, &account_keys).await;
+    report.record_phase("fetch", fetch_start);
+    let snapshot = match snapshot {
+        Ok(snapshot) => snapshot,
+        Err(err) => {
+            error!("failed to fetch reserves {:#?}", err);
+            report.set_fatal(FailureReason::Rpc(err.to_string()));
+            return finish_report(report);
+        }
+    };
+    debug!(
+        "fetched {} reserves at slot {}",
+        account_keys.len(),
+        snapshot.slot
+    );
+    let store_start = Utc::now();
+    for (rate, reserve) in reserves.into_iter() {
+        let key = ItemKey::InterestRate {
+            asset: rate.asset.clone(),
+            platform: rate.platform.to_string(),
+        };
+        let curve = match snapshot.accounts.get(&reserve) {
+            Some(account) => reserve_curve(&rate.platform, account),
+            None => Err(anyhow!("reserve {} not found", reserve)),
+        };
+        let curve = match curve {
+            Ok(curve) => curve,
+            Err(err) => {
+                error!("failed to decode reserve config for {}: {:#?}", key, err);
+                report.record_failure(key, FailureReason::Decode(err.to_string()));
+                continue;
+            }
+        };
+        match sink.put(&Record::InterestRateCurve {
+            platform: rate.platform.to_string(),
+            asset: rate.asset.clone(),
+            min_borrow_rate: curve.min_borrow_rate,
+            max_borrow_rate: curve.max_borrow_rate,
+            optimal_borrow_rate: curve.optimal_borrow_rate,
+            optimal_utilization_rate: curve.optimal_utilization_rate,
+            degen_borrow_rate: curve.degen_borrow_rate,
+            degen_utilization_rate: curve.degen_utilization_rate,
+            scraped_at: store_start,
+            slot: snapshot.slot,
+        }) {
+            Ok(outcome) => report.record(key, outcome.into()),
+            Err(err) => {
+                error!("failed to put interest rate curve for {}: {:#?}", key, err);
+                report.record_failure(key, FailureReason::Database(err.to_string()));
+            }
+        }
+    }
+    report.record_phase("store", store_start);
+    finish_report(report)
+}
+
+fn finish_report(report: ScrapeReport) -> ScrapeReport {
+    let report = report.finish();
+    metrics::observe_report(&report);
+    info!("{}", report);
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fixed::types::I80F48;
+    use tulip_state::lending_reserve::{Reserve, ReserveConfig};
+
+    #[test]
+    fn test_reserve_curve() {
+        let reserve = Reserve {
+            config: ReserveConfig {
+                optimal_utilization_rate: 80,
+                degen_utilization_rate: 90,
+                min_borrow_rate: 0,
+                optimal_borrow_rate: 10,
+                degen_borrow_rate: 50,
+                max_borrow_rate: 150,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut data = vec![0_u8; Reserve::LEN];
+        reserve.pack_into_slice(&mut data);
+        let account = Account {
+            data,
+            ..Default::default()
+        };
+        let curve = reserve_curve(&Platform::Tulip, &account).unwrap();
+        assert_eq!(
+            curve,
+            ReserveCurve {
+                min_borrow_rate: 0_f64,
+                max_borrow_rate: 150_f64,
+                optimal_borrow_rate: 10_f64,
+                optimal_utilization_rate: 80_f64,
+                degen_borrow_rate: 50_f64,
+                degen_utilization_rate: 90_f64,
+            }
+        );
+        // a spl lending reserve is not a mango root bank
+        assert!(reserve_curve(&Platform::MangoV3, &account).is_err());
+    }
+
+    #[test]
+    fn test_mango_reserve_curve() {
+        let mut root_bank: RootBank = bytemuck::Zeroable::zeroed();
+        root_bank.optimal_util = I80F48::from_num(0.7);
+        root_bank.optimal_rate = I80F48::from_num(0.06);
+        root_bank.max_rate = I80F48::from_num(1.5);
+        let account = Account {
+            data: bytemuck::bytes_of(&root_bank).to_vec(),
+            ..Default::default()
+        };
+        let curve = reserve_curve(&Platform::MangoV3, &account).unwrap();
+        assert_eq!(curve.min_borrow_rate, 0_f64);
+        assert!((curve.optimal_utilization_rate - 70_f64).abs() < 1e-9);
+        assert!((curve.optimal_borrow_rate - 6_f64).abs() < 1e-9);
+        assert!((curve.max_borrow_rate - 150_f64).abs() < 1e-9);
+        assert_eq!(curve.degen_borrow_rate, 0_f64);
+        assert_eq!(curve.degen_utilization_rate, 0_f64);
+    }
+}
@@ -0,0 +1,372 @@
+//! bandwidth efficient program account scans. instead of downloading the full data of
+//! every account on every run, a scan fetches only a slice of each account which is
+//! compared against the slice observed by the previous scan, fetching the full data
+//! of new or changed accounts afterwards. large scans can additionally be split into
+//! shards using memcmp filters on the leading bytes of a key stored in the account,
+//! keeping each getProgramAccounts response under rpc limits
+
+use anchor_client::solana_sdk::account::Account;
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use anyhow::{anyhow, Result};
+use log::debug;
+use oracle::rate_lookup::account_source::AccountSource;
+use oracle::rate_lookup::lookup::MAX_MULTIPLE_ACCOUNTS;
+use serde::{Deserialize, Serialize};
+use solana_account_decoder::{UiAccountEncoding, UiDataSliceConfig};
+use solana_client::{
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType},
+};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+/// the largest supported shard prefix, 2 bytes splits a scan into 65536 shards. as
+/// shards are requested one after another, longer prefixes would issue millions of requests
+pub const MAX_SHARD_PREFIX_LEN: usize = 2;
+
+/// options which control how program accounts are scanned
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ScanOpts {
+    /// when true, only a slice of each account is fetched, and the full data is only
+    /// fetched for accounts which are new, or whose slice changed since the last scan
+    #[serde(default)]
+    pub sliced: bool,
+    /// number of leading bytes of the shard key used to split the scan into shards,
+    /// 0 disables sharding, 1 splits the scan into 256 requests, and 2 into 65536.
+    /// each shard is a separate getProgramAccounts request, sent one after another,
+    /// and read at its own slot. at most `MAX_SHARD_PREFIX_LEN`
+    #[serde(default)]
+    pub shard_prefix_len: usize,
+    /// every n-th sliced scan forgets the previously observed slices, treating every
+    /// account as changed. this bounds how long a change outside of the slice can go
+    /// unnoticed. 0 disables full refreshes
+    #[serde(default)]
+    pub full_refresh_runs: u32,
+}
+
+impl ScanOpts {
+    /// returns an error if the options can't be used to scan
+    pub fn validate(&self) -> Result<()> {
+        if self.shard_prefix_len > MAX_SHARD_PREFIX_LEN {
+            return Err(anyhow!(
+                "shard prefix length {} exceeds {}",
+                self.shard_prefix_len,
+                MAX_SHARD_PREFIX_LEN
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// describes the accounts to scan
+#[derive(Clone, Debug)]
+pub struct ScanRequest {
+    pub program_id: Pubkey,
+    pub filters: Vec<RpcFilterType>,
+    pub encoding: Option<UiAccountEncoding>,
+    /// offset of the key within the account data that shards are split by
+    pub shard_offset: usize,
+    /// the slice of account data fetched by sliced scans, used to detect changes
+    pub slice: UiDataSliceConfig,
+    /// when true, sliced scans fetch the full data of new or changed accounts. scrapers
+    /// which only need the fields within `slice` can skip the follow-up fetch
+    pub fetch_full: bool,
+}
+
+/// an account returned by a scan
+#[derive(Clone, Debug)]
+pub struct ScannedAccount {
+    pub key: Pubkey,
+    /// the full account data, or the sliced data if the scan didn't fetch full data
+    pub account: Account,
+    /// the slice of account data used to detect changes
+    pub fingerprint: Vec<u8>,
+}
+
+/// the result of a scan
+#[derive(Clone, Debug, Default)]
+pub struct ScanResult {
+    /// the oldest slot any shard was observed at
+    pub slot: u64,
+    /// accounts which are new, or changed since the previous scan
+    pub changed: Vec<ScannedAccount>,
+    /// accounts which were unchanged since the previous scan
+    pub unchanged: Vec<Pubkey>,
+}
+
+/// the slices observed by previous scans, carried between runs of a scraper
+#[derive(Clone, Debug, Default)]
+pub struct ScanCache {
+    fingerprints: HashMap<Pubkey, Vec<u8>>,
+    runs: u32,
+}
+
+impl ScanCache {
+    /// records the slice of an account once it has been processed, accounts
+    /// which fail to process are not recorded so the next scan retries them
+    pub fn record(&mut self, key: Pubkey, fingerprint: Vec<u8>) {
+        self.fingerprints.insert(key, fingerprint);
+    }
+    pub fn len(&self) -> usize {
+        self.fingerprints.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.fingerprints.is_empty()
+    }
+}
+
+/// scans the program accounts described by `request`
+pub async fn scan_program_accounts(
+    source: &dyn AccountSource,
+    request: &ScanRequest,
+    opts: ScanOpts,
+    cache: &Mutex<ScanCache>,
+) -> Result<ScanResult> {
+    opts.validate()?;
+    let data_slice = if opts.sliced {
+        Some(request.slice)
+    } else {
+        None
+    };
+    let mut slot = u64::MAX;
+    let mut accounts = Vec::new();
+    for shard in shard_filters(request.shard_offset, opts.shard_prefix_len)? {
+        let mut filters = request.filters.clone();
+        filters.extend(shard);
+        let (shard_slot, mut shard_accounts) = source
+            .get_program_accounts(
+                &request.program_id,
+                RpcProgramAccountsConfig {
+                    filters: Some(filters),
+                    with_context: Some(true),
+                    account_config: RpcAccountInfoConfig {
+                        encoding: request.encoding,
+                        data_slice,
+                        commitment: Some(source.commitment()),
+                    },
+                },
+            )
+            .await?;
+        slot = slot.min(shard_slot);
+        accounts.append(&mut shard_accounts);
+    }
+    if slot == u64::MAX {
+        slot = 0;
+    }
+    if !opts.sliced {
+        return Ok(ScanResult {
+            slot,
+            changed: accounts
+                .into_iter()
+                .map(|(key, account)| ScannedAccount {
+                    fingerprint: slice_data(&account.data, request.slice),
+                    key,
+                    account,
+                })
+                .collect(),
+            unchanged: Vec::new(),
+        });
+    }
+    let mut changed = Vec::new();
+    let mut unchanged = Vec::new();
+    {
+        let mut cache = match cache.lock() {
+            Ok(cache) => cache,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        cache.runs = cache.runs.wrapping_add(1);
+        if opts.full_refresh_runs > 0 && cache.runs % opts.full_refresh_runs == 0 {
+            debug!("performing full refresh of {}", request.program_id);
+            cache.fingerprints.clear();
+        }
+        // forget accounts which no longer exist
+        let seen: HashSet<Pubkey> = accounts.iter().map(|(key, _)| *key).collect();
+        cache.fingerprints.retain(|key, _| seen.contains(key));
+        for (key, account) in accounts.into_iter() {
+            if cache.fingerprints.get(&key) == Some(&account.data) {
+                unchanged.push(key);
+            } else {
+                changed.push(ScannedAccount {
+                    key,
+                    fingerprint: account.data.clone(),
+                    account,
+                });
+            }
+        }
+    }
+    if request.fetch_full {
+        fetch_full_data(source, &mut changed).await?;
+    }
+    Ok(ScanResult {
+        slot,
+        changed,
+        unchanged,
+    })
+}
+
+/// replaces the sliced data of the given accounts with their full data. accounts
+/// which were closed between the scan and the follow-up fetch are dropped
+async fn fetch_full_data(
+    source: &dyn AccountSource,
+    scanned: &mut Vec<ScannedAccount>,
+) -> Result<()> {
+    let mut full = Vec::with_capacity(scanned.len());
+    for chunk in scanned.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        let keys = chunk.iter().map(|scanned| scanned.key).collect::<Vec<_>>();
+        let (_, accounts) = source.get_multiple_accounts(&keys).await?;
+        for (scanned, account) in chunk.iter().zip(accounts) {
+            if let Some(account) = account {
+                full.push(ScannedAccount {
+                    key: scanned.key,
+                    account,
+                    fingerprint: scanned.fingerprint.clone(),
+                });
+            }
+        }
+    }
+    *scanned = full;
+    Ok(())
+}
+
+/// returns one set of filters per shard, each matching accounts whose shard key begins
+/// with a different prefix. a prefix length of 0 returns a single unfiltered shard, and
+/// prefixes longer than `MAX_SHARD_PREFIX_LEN` are rejected
+pub fn shard_filters(shard_offset: usize, prefix_len: usize) -> Result<Vec<Option<RpcFilterType>>> {
+    if prefix_len > MAX_SHARD_PREFIX_LEN {
+        return Err(anyhow!(
+            "shard prefix length {} exceeds {}",
+            prefix_len,
+            MAX_SHARD_PREFIX_LEN
+        ));
+    }
+    if prefix_len == 0 {
+        return Ok(vec![None]);
+    }
+    Ok((0..256_usize.pow(prefix_len as u32))
+        .map(|shard| {
+            let prefix = &shard.to_be_bytes()[std::mem::size_of::<usize>() - prefix_len..];
+            Some(RpcFilterType::Memcmp(Memcmp {
+                offset: shard_offset,
+                bytes: MemcmpEncodedBytes::Base58(bs58::encode(prefix).into_string()),
+                encoding: None,
+            }))
+        })
+        .collect())
+}
+
+fn slice_data(data: &[u8], slice: UiDataSliceConfig) -> Vec<u8> {
+    let start = slice.offset.min(data.len());
+    let end = slice.offset.saturating_add(slice.length).min(data.len());
+    data[start..end].to_vec()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use oracle::rate_lookup::account_source::SnapshotAccountSource;
+
+    #[tokio::test]
+    async fn test_sliced_sharded_scan() {
+        let program_id = Pubkey::new_unique();
+        let mut source = SnapshotAccountSource {
+            slot: 10,
+            ..Default::default()
+        };
+        let keys = (0..4).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+        for (idx, key) in keys.iter().enumerate() {
+            source.accounts.insert(
+                *key,
+                Account {
+                    lamports: 1,
+                    // the shard key is the first byte, the slice the first two bytes
+                    data: vec![idx as u8 % 2, idx as u8, 42, 42],
+                    owner: program_id,
+                    executable: false,
+                    rent_epoch: 0,
+                },
+            );
+        }
+        let request = ScanRequest {
+            program_id,
+            filters: vec![RpcFilterType::DataSize(4)],
+            encoding: Some(UiAccountEncoding::Base64),
+            shard_offset: 0,
+            slice: UiDataSliceConfig {
+                offset: 0,
+                length: 2,
+            },
+            fetch_full: true,
+        };
+        let opts = ScanOpts {
+            sliced: true,
+            shard_prefix_len: 1,
+            full_refresh_runs: 3,
+        };
+        let cache = Mutex::new(ScanCache::default());
+        let result = scan_program_accounts(&source, &request, opts, &cache)
+            .await
+            .unwrap();
+        assert_eq!(result.slot, 10);
+        assert_eq!(result.changed.len(), 4);
+        assert!(result.unchanged.is_empty());
+        // the follow-up fetch returns the full account data
+        assert!(result
+            .changed
+            .iter()
+            .all(|scanned| scanned.account.data.len() == 4));
+        for scanned in result.changed.iter().skip(1) {
+            cache
+                .lock()
+                .unwrap()
+                .record(scanned.key, scanned.fingerprint.clone());
+        }
+
+        // only the unrecorded, and changed accounts are returned
+        source.accounts.get_mut(&keys[3]).unwrap().data[1] = 9;
+        let result = scan_program_accounts(&source, &request, opts, &cache)
+            .await
+            .unwrap();
+        let mut changed = result.changed.iter().map(|s| s.key).collect::<Vec<_>>();
+        changed.sort();
+        let mut expected = vec![keys[0], keys[3]];
+        expected.sort();
+        assert_eq!(changed, expected);
+        assert_eq!(result.unchanged.len(), 2);
+
+        // the third run is a full refresh
+        let result = scan_program_accounts(&source, &request, opts, &cache)
+            .await
+            .unwrap();
+        assert_eq!(result.changed.len(), 4);
+        assert!(cache.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_shard_filters() {
+        assert_eq!(shard_filters(42, 0).unwrap(), vec![None]);
+        let filters = shard_filters(42, 1).unwrap();
+        assert_eq!(filters.len(), 256);
+        match &filters[255] {
+            Some(RpcFilterType::Memcmp(memcmp)) => {
+                assert_eq!(memcmp.offset, 42);
+                // the shard key starts at offset 42 of the account data
+                let mut data = vec![0; 43];
+                data[42] = 255;
+                assert!(memcmp.bytes_match(&data));
+                data[42] = 254;
+                assert!(!memcmp.bytes_match(&data));
+            }
+            _ => panic!("expected memcmp filter"),
+        }
+        assert_eq!(shard_filters(0, 2).unwrap().len(), 65536);
+        // longer prefixes would issue millions of requests
+        assert!(shard_filters(0, 3).is_err());
+        assert!(shard_filters(0, 9).is_err());
+        let opts = ScanOpts {
+            shard_prefix_len: 3,
+            ..Default::default()
+        };
+        assert!(opts.validate().is_err());
+    }
+}
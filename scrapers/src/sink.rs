@@ -0,0 +1,511 @@
+//! provides the `Sink` trait which receives the records produced by scrapers,
+//! allowing a scrape to be written to the database, or dry-run to stdout
+
+use crate::metrics;
+use anyhow::{anyhow, Result};
+use chrono::prelude::*;
+use db::client::{DBClient, PutOutcome};
+use db::defaults::DBError;
+use db::filters::{
+    InterestRateCurveMatcher, LendingOptimizerDistributionMatcher, V1LiquidatedPositionMatcher,
+    V1UserFarmMatcher,
+};
+use db::models::{
+    InterestRate, InterestRateCurve, LendingOptimizerDistribution, V1LiquidatedPosition, V1UserFarm,
+};
+use diesel::PgConnection;
+use serde::Serialize;
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// a record produced by a scraper, tagged with the table it is destined for
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "table", rename_all = "snake_case")]
+pub enum Record {
+    InterestRate {
+        platform: String,
+        asset: String,
+        borrow_rate: f64,
+        utilization_rate: f64,
+        lending_rate: f64,
+        /// the lending rate after protocol take rates
+        net_lending_rate: f64,
+        /// compounded equivalents of the borrow, lending and net lending rates
+        borrow_apy: f64,
+        lending_apy: f64,
+        net_lending_apy: f64,
+        /// the reserve's interest indexes, 0 for platforms without them
+        cumulative_borrow_rate: f64,
+        collateral_exchange_rate: f64,
+        available_amount: f64,
+        borrowed_amount: f64,
+        scraped_at: DateTime<Utc>,
+        /// the slot the account data was observed at
+        slot: u64,
+        /// the commitment level the account data was read at
+        commitment: String,
+    },
+    InterestRateCurve {
+        platform: String,
+        asset: String,
+        min_borrow_rate: f64,
+        max_borrow_rate: f64,
+        optimal_borrow_rate: f64,
+        optimal_utilization_rate: f64,
+        degen_borrow_rate: f64,
+        degen_utilization_rate: f64,
+        scraped_at: DateTime<Utc>,
+        /// the slot the reserve config was observed at
+        slot: u64,
+    },
+    LendingOptimizerDistribution {
+        vault_name: String,
+        platforms: Vec<String>,
+        deposited_balances: Vec<f64>,
+    },
+    V1ObligationAccount {
+        account: String,
+        authority: String,
+    },
+    V1UserFarm {
+        authority: String,
+        account_address: String,
+        leveraged_farm: String,
+        obligations: Vec<String>,
+        obligation_indexes: Vec<i32>,
+    },
+    V1ObligationLtv {
+        authority: String,
+        user_farm: String,
+        account_address: String,
+        leveraged_farm: String,
+        ltv: f64,
+        scraped_at: DateTime<Utc>,
+        slot: u64,
+        commitment: String,
+    },
+    V1LiquidatedPosition {
+        liquidation_event_id: String,
+        temp_liquidation_account: String,
+        authority: String,
+        user_farm: String,
+        obligation: String,
+        leveraged_farm: String,
+        started_at: DateTime<Utc>,
+        ended_at: Option<DateTime<Utc>>,
+    },
+}
+
+impl Record {
+    /// returns the name of the table the record is destined for
+    pub fn table(&self) -> &'static str {
+        match self {
+            Record::InterestRate { .. } => "interest_rate",
+            Record::InterestRateCurve { .. } => "interest_rate_curve",
+            Record::LendingOptimizerDistribution { .. } => "lending_optimizer_distribution",
+            Record::V1ObligationAccount { .. } => "v1_obligation_account",
+            Record::V1UserFarm { .. } => "v1_user_farm",
+            Record::V1ObligationLtv { .. } => "v1_obligation_ltv",
+            Record::V1LiquidatedPosition { .. } => "v1_liquidated_position",
+        }
+    }
+}
+
+/// receives records produced by scrapers
+pub trait Sink {
+    fn put(&self, record: &Record) -> Result<PutOutcome>;
+    /// returns the user farms stored by previous user farm scrapes, which scrapers use
+    /// to resolve the user farm owning an obligation. sinks which don't store records
+    /// have nothing to return
+    fn v1_user_farms(&self) -> Result<Vec<V1UserFarm>> {
+        Ok(Vec::new())
+    }
+    /// returns the liquidated positions stored by previous liquidation scrapes,
+    /// allowing liquidation tracking to resume where it left off after a restart
+    fn v1_liquidated_positions(&self) -> Result<Vec<V1LiquidatedPosition>> {
+        Ok(Vec::new())
+    }
+    /// returns the interest rate curve of every platform lending `asset`, paired
+    /// with the most recent interest rate sample of the platform. platforms which
+    /// have a curve but no samples are omitted
+    fn lending_markets(&self, _asset: &str) -> Result<Vec<(InterestRateCurve, InterestRate)>> {
+        Ok(Vec::new())
+    }
+    /// returns the last distribution stored for the lending optimizer vault
+    fn lending_optimizer_distribution(
+        &self,
+        _vault_name: &str,
+    ) -> Result<Option<LendingOptimizerDistribution>> {
+        Ok(None)
+    }
+}
+
+/// writes records into the database
+pub struct DbSink<'a> {
+    pub client: Arc<DBClient<'a>>,
+}
+
+impl<'a> DbSink<'a> {
+    pub fn new(conn: &'a PgConnection, oob_limit: f64) -> Self {
+        Self {
+            client: Arc::new(DBClient { conn, oob_limit }),
+        }
+    }
+}
+
+impl<'a> Sink for DbSink<'a> {
+    fn put(&self, record: &Record) -> Result<PutOutcome> {
+        let start = Instant::now();
+        let outcome = self.put_record(record);
+        metrics::observe_db_write(record.table(), start);
+        outcome
+    }
+    fn v1_user_farms(&self) -> Result<Vec<V1UserFarm>> {
+        Ok(self.client.get_v1_user_farm(&V1UserFarmMatcher::All)?)
+    }
+    fn v1_liquidated_positions(&self) -> Result<Vec<V1LiquidatedPosition>> {
+        Ok(self
+            .client
+            .get_v1_liquidated_position(&V1LiquidatedPositionMatcher::All)?)
+    }
+    fn lending_markets(&self, asset: &str) -> Result<Vec<(InterestRateCurve, InterestRate)>> {
+        let curves = self
+            .client
+            .get_interest_rate_curve(&InterestRateCurveMatcher::Asset(vec![asset.to_string()]))?;
+        let mut markets = Vec::with_capacity(curves.len());
+        for curve in curves.into_iter() {
+            if let Some(rate) = self
+                .client
+                .get_latest_interest_rate(&curve.platform, &curve.asset)?
+            {
+                markets.push((curve, rate));
+            }
+        }
+        Ok(markets)
+    }
+    fn lending_optimizer_distribution(
+        &self,
+        vault_name: &str,
+    ) -> Result<Option<LendingOptimizerDistribution>> {
+        Ok(self
+            .client
+            .get_lending_optimizer_distribution(&LendingOptimizerDistributionMatcher::VaultName(
+                vec![vault_name.to_string()],
+            ))?
+            .into_iter()
+            .next())
+    }
+}
+
+impl<'a> DbSink<'a> {
+    fn put_record(&self, record: &Record) -> Result<PutOutcome> {
+        match record {
+            Record::InterestRate {
+                platform,
+                asset,
+                borrow_rate,
+                utilization_rate,
+                lending_rate,
+                net_lending_rate,
+                borrow_apy,
+                lending_apy,
+                net_lending_apy,
+                cumulative_borrow_rate,
+                collateral_exchange_rate,
+                available_amount,
+                borrowed_amount,
+                scraped_at,
+                slot,
+                commitment,
+            } => {
+                self.client.put_interest_rate(
+                    platform.clone(),
+                    asset.clone(),
+                    *borrow_rate,
+                    *utilization_rate,
+                    *lending_rate,
+                    *net_lending_rate,
+                    *borrow_apy,
+                    *lending_apy,
+                    *net_lending_apy,
+                    *cumulative_borrow_rate,
+                    *collateral_exchange_rate,
+                    *available_amount,
+                    *borrowed_amount,
+                    *scraped_at,
+                    *slot,
+                    commitment,
+                )?;
+                // every scrape records a new interest rate sample
+                Ok(PutOutcome::Inserted)
+            }
+            Record::InterestRateCurve {
+                platform,
+                asset,
+                min_borrow_rate,
+                max_borrow_rate,
+                optimal_borrow_rate,
+                optimal_utilization_rate,
+                degen_borrow_rate,
+                degen_utilization_rate,
+                scraped_at,
+                slot,
+            } => self.client.upsert_interest_rate_curve(
+                platform,
+                asset,
+                *min_borrow_rate,
+                *max_borrow_rate,
+                *optimal_borrow_rate,
+                *optimal_utilization_rate,
+                *degen_borrow_rate,
+                *degen_utilization_rate,
+                *scraped_at,
+                *slot,
+            ),
+            Record::LendingOptimizerDistribution {
+                vault_name,
+                platforms,
+                deposited_balances,
+            } => self.client.put_lending_optimizer_distribution(
+                vault_name.clone(),
+                platforms.clone(),
+                deposited_balances.clone(),
+            ),
+            Record::V1ObligationAccount { account, authority } => {
+                self.client.put_v1_obligation_account(account, authority)
+            }
+            Record::V1UserFarm {
+                authority,
+                account_address,
+                leveraged_farm,
+                obligations,
+                obligation_indexes,
+            } => self.client.put_v1_user_farm(
+                authority,
+                account_address,
+                leveraged_farm,
+                obligations,
+                obligation_indexes,
+            ),
+            Record::V1ObligationLtv {
+                authority,
+                user_farm,
+                account_address,
+                leveraged_farm,
+                ltv,
+                scraped_at,
+                slot,
+                commitment,
+            } => self.client.put_v1_obligation_ltv(
+                authority,
+                user_farm,
+                account_address,
+                leveraged_farm,
+                *ltv,
+                *scraped_at,
+                *slot,
+                commitment,
+            ),
+            Record::V1LiquidatedPosition {
+                liquidation_event_id,
+                temp_liquidation_account,
+                authority,
+                user_farm,
+                obligation,
+                leveraged_farm,
+                started_at,
+                ended_at,
+            } => {
+                let result = self.client.put_v1_liquidated_position(
+                    temp_liquidation_account,
+                    authority,
+                    user_farm,
+                    liquidation_event_id,
+                    obligation,
+                    leveraged_farm,
+                    *started_at,
+                    *ended_at,
+                );
+                match result {
+                    // the position was already opened, nothing needs to be written
+                    Err(err) if is_position_already_exists(&err) => Ok(PutOutcome::Skipped),
+                    result => result,
+                }
+            }
+        }
+    }
+}
+
+fn is_position_already_exists(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<DBError>(),
+        Some(DBError::V1PositionAlreadyExists(_))
+    )
+}
+
+/// a dry-run sink which writes each record as a line of json instead of storing
+/// it. as nothing is written to the database, every record is reported as skipped.
+///
+/// jobs which depend on previously stored records read them through the optional
+/// `reader`, which is never written to. without a reader those reads fail, rather
+/// than letting the job run against an empty set of records
+pub struct StdoutSink<'a, W: Write = std::io::Stdout> {
+    writer: Mutex<W>,
+    reader: Option<&'a dyn Sink>,
+}
+
+impl<'a> StdoutSink<'a> {
+    pub fn new() -> Self {
+        Self::with_writer(std::io::stdout())
+    }
+}
+
+impl<'a> Default for StdoutSink<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, W: Write> StdoutSink<'a, W> {
+    pub fn with_writer(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+            reader: None,
+        }
+    }
+    /// reads previously stored records from `reader`, typically a `DbSink`
+    pub fn with_reader(mut self, reader: &'a dyn Sink) -> Self {
+        self.reader = Some(reader);
+        self
+    }
+    pub fn into_inner(self) -> W {
+        match self.writer.into_inner() {
+            Ok(writer) => writer,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+    fn reader(&self, records: &str) -> Result<&'a dyn Sink> {
+        self.reader.ok_or_else(|| {
+            anyhow!(
+                "dry-run can't read stored {} without a database, set --database-url",
+                records
+            )
+        })
+    }
+}
+
+impl<'a, W: Write> Sink for StdoutSink<'a, W> {
+    fn put(&self, record: &Record) -> Result<PutOutcome> {
+        let line = serde_json::to_string(record)?;
+        let mut writer = match self.writer.lock() {
+            Ok(writer) => writer,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        writeln!(writer, "{}", line)?;
+        Ok(PutOutcome::Skipped)
+    }
+    fn v1_user_farms(&self) -> Result<Vec<V1UserFarm>> {
+        self.reader("v1 user farms")?.v1_user_farms()
+    }
+    fn v1_liquidated_positions(&self) -> Result<Vec<V1LiquidatedPosition>> {
+        self.reader("v1 liquidated positions")?
+            .v1_liquidated_positions()
+    }
+    fn lending_markets(&self, asset: &str) -> Result<Vec<(InterestRateCurve, InterestRate)>> {
+        self.reader("lending markets")?.lending_markets(asset)
+    }
+    fn lending_optimizer_distribution(
+        &self,
+        vault_name: &str,
+    ) -> Result<Option<LendingOptimizerDistribution>> {
+        self.reader("lending optimizer distributions")?
+            .lending_optimizer_distribution(vault_name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stdout_sink_json_lines() {
+        let sink = StdoutSink::with_writer(Vec::new());
+        assert_eq!(
+            sink.put(&Record::V1ObligationAccount {
+                account: "account1".to_string(),
+                authority: "authority1".to_string(),
+            })
+            .unwrap(),
+            PutOutcome::Skipped
+        );
+        sink.put(&Record::V1UserFarm {
+            authority: "authority1".to_string(),
+            account_address: "account2".to_string(),
+            leveraged_farm: "farm".to_string(),
+            obligations: vec!["obligation".to_string()],
+            obligation_indexes: vec![1],
+        })
+        .unwrap();
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            r#"{"table":"v1_obligation_account","account":"account1","authority":"authority1"}"#
+        );
+        let value: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(value["table"], "v1_user_farm");
+        assert_eq!(value["obligation_indexes"][0], 1);
+    }
+
+    /// returns a fixed set of stored records, and fails any writes
+    struct FixtureSink;
+
+    impl Sink for FixtureSink {
+        fn put(&self, _record: &Record) -> Result<PutOutcome> {
+            Err(anyhow!("read only"))
+        }
+        fn v1_user_farms(&self) -> Result<Vec<V1UserFarm>> {
+            Ok(vec![V1UserFarm {
+                id: 1,
+                account_address: "account2".to_string(),
+                authority: "authority1".to_string(),
+                obligations: vec!["obligation".to_string()],
+                obligation_indexes: vec![1],
+                leveraged_farm: "farm".to_string(),
+                data_hash: String::new(),
+            }])
+        }
+    }
+
+    #[test]
+    fn test_stdout_sink_reader() {
+        // without a reader, jobs which depend on stored records fail instead
+        // of running against an empty set of records
+        let sink = StdoutSink::with_writer(Vec::new());
+        let err = sink.v1_user_farms().unwrap_err();
+        assert!(err.to_string().contains("--database-url"));
+        assert!(sink.v1_liquidated_positions().is_err());
+        assert!(sink.lending_markets("USDC").is_err());
+        assert!(sink.lending_optimizer_distribution("LENDING-USDC").is_err());
+
+        let reader = FixtureSink;
+        let sink = StdoutSink::with_writer(Vec::new()).with_reader(&reader);
+        let farms = sink.v1_user_farms().unwrap();
+        assert_eq!(farms.len(), 1);
+        assert_eq!(farms[0].account_address, "account2");
+        // records are still written to the writer, never to the reader
+        assert_eq!(
+            sink.put(&Record::V1ObligationAccount {
+                account: "account1".to_string(),
+                authority: "authority1".to_string(),
+            })
+            .unwrap(),
+            PutOutcome::Skipped
+        );
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        assert!(output.starts_with(r#"{"table":"v1_obligation_account""#));
+    }
+}
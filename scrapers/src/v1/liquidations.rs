@@ -0,0 +1,370 @@
+//! tracks the lifecycle of v1 liquidations. a position is considered to be under
+//! liquidation while its temp liquidation account exists, so successive scans of the
+//! temp liquidation accounts are compared to open and close `v1_liquidated_position` rows
+
+use super::find_temp_liquidation_accounts;
+use crate::report::{FailureReason, ItemKey, ScrapeReport};
+use crate::sink::{Record, Sink};
+use anchor_client::solana_sdk::pubkey::Pubkey;
+use chrono::prelude::*;
+use config::Configuration;
+use db::models::{V1LiquidatedPosition, V1UserFarm};
+use log::{error, info, warn};
+use oracle::rate_lookup::account_source::AccountSource;
+use solana_sdk::hash::hashv;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// a liquidation which has been observed to start, but not yet end
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpenPosition {
+    pub liquidation_event_id: String,
+    pub temp_liquidation_account: String,
+    pub authority: String,
+    pub user_farm: String,
+    pub obligation: String,
+    pub leveraged_farm: String,
+    pub started_at: DateTime<Utc>,
+}
+
+/// a change in the state of a liquidation, produced by comparing scans
+#[derive(Clone, Debug, PartialEq)]
+pub enum PositionChange {
+    /// a temp liquidation account appeared
+    Opened(OpenPosition),
+    /// a temp liquidation account disappeared
+    Closed(OpenPosition, DateTime<Utc>),
+}
+
+impl PositionChange {
+    pub fn position(&self) -> &OpenPosition {
+        match self {
+            PositionChange::Opened(position) => position,
+            PositionChange::Closed(position, _) => position,
+        }
+    }
+    pub fn to_record(&self) -> Record {
+        let (position, ended_at) = match self {
+            PositionChange::Opened(position) => (position, None),
+            PositionChange::Closed(position, ended_at) => (position, Some(*ended_at)),
+        };
+        Record::V1LiquidatedPosition {
+            liquidation_event_id: position.liquidation_event_id.clone(),
+            temp_liquidation_account: position.temp_liquidation_account.clone(),
+            authority: position.authority.clone(),
+            user_farm: position.user_farm.clone(),
+            obligation: position.obligation.clone(),
+            leveraged_farm: position.leveraged_farm.clone(),
+            started_at: position.started_at,
+            ended_at,
+        }
+    }
+}
+
+/// remembers the open liquidations between scans. the tracker is seeded from the
+/// positions previously stored in the sink, so a restart resumes open positions
+/// instead of opening them a second time
+#[derive(Clone, Debug, Default)]
+pub struct LiquidationTracker {
+    /// maps temp liquidation account -> open position
+    open: HashMap<String, OpenPosition>,
+    /// maps temp liquidation account -> number of positions which used it and have ended
+    closed: HashMap<String, u64>,
+    seeded: bool,
+}
+
+impl LiquidationTracker {
+    pub fn is_seeded(&self) -> bool {
+        self.seeded
+    }
+    /// returns the currently open positions, keyed by temp liquidation account
+    pub fn open_positions(&self) -> &HashMap<String, OpenPosition> {
+        &self.open
+    }
+    /// replaces the tracker state with previously stored positions
+    pub fn seed(&mut self, positions: &[V1LiquidatedPosition]) {
+        self.open.clear();
+        self.closed.clear();
+        for position in positions.iter() {
+            if position.ended_at.is_some() {
+                *self
+                    .closed
+                    .entry(position.temp_liquidation_account.clone())
+                    .or_default() += 1;
+            } else {
+                self.open.insert(
+                    position.temp_liquidation_account.clone(),
+                    OpenPosition {
+                        liquidation_event_id: position.liquidation_event_id.clone(),
+                        temp_liquidation_account: position.temp_liquidation_account.clone(),
+                        authority: position.authority.clone(),
+                        user_farm: position.user_farm.clone(),
+                        obligation: position.obligation.clone(),
+                        leveraged_farm: position.leveraged_farm.clone(),
+                        started_at: position.started_at,
+                    },
+                );
+            }
+        }
+        self.seeded = true;
+    }
+    /// compares the temp liquidation accounts found by a scan at `now` against the open
+    /// positions, returning the positions which opened and closed. `current` contains
+    /// (temp liquidation account, obligation) pairs, and `owners` maps obligations to
+    /// the user farm which owns them. the tracker is not modified, call `apply` once a
+    /// change has been stored so that failed writes are retried by the next scan
+    pub fn diff(
+        &self,
+        now: DateTime<Utc>,
+        current: &[(Pubkey, Pubkey)],
+        owners: &HashMap<&str, &V1UserFarm>,
+    ) -> Vec<PositionChange> {
+        let mut changes = Vec::new();
+        let mut seen = Vec::with_capacity(current.len());
+        for (temp_account, obligation) in current.iter() {
+            let temp_account = temp_account.to_string();
+            if !self.open.contains_key(&temp_account) {
+                let obligation = obligation.to_string();
+                let sequence = self.closed.get(&temp_account).copied().unwrap_or_default();
+                let (authority, user_farm, leveraged_farm) = match owners.get(obligation.as_str()) {
+                    Some(owner) => (
+                        owner.authority.clone(),
+                        owner.account_address.clone(),
+                        owner.leveraged_farm.clone(),
+                    ),
+                    None => {
+                        warn!(
+                            "no user farm found for liquidated obligation {}",
+                            obligation
+                        );
+                        Default::default()
+                    }
+                };
+                changes.push(PositionChange::Opened(OpenPosition {
+                    liquidation_event_id: liquidation_event_id(
+                        &temp_account,
+                        &obligation,
+                        sequence,
+                    ),
+                    temp_liquidation_account: temp_account.clone(),
+                    authority,
+                    user_farm,
+                    obligation,
+                    leveraged_farm,
+                    started_at: now,
+                }));
+            }
+            seen.push(temp_account);
+        }
+        let mut closed = self
+            .open
+            .values()
+            .filter(|position| !seen.contains(&position.temp_liquidation_account))
+            .map(|position| PositionChange::Closed(position.clone(), now))
+            .collect::<Vec<_>>();
+        // hashmap iteration order is random, so sort to keep writes deterministic
+        closed.sort_unstable_by(|a, b| {
+            a.position()
+                .temp_liquidation_account
+                .cmp(&b.position().temp_liquidation_account)
+        });
+        changes.append(&mut closed);
+        changes
+    }
+    /// records that a change returned by `diff` has been stored
+    pub fn apply(&mut self, change: &PositionChange) {
+        match change {
+            PositionChange::Opened(position) => {
+                self.open
+                    .insert(position.temp_liquidation_account.clone(), position.clone());
+            }
+            PositionChange::Closed(position, _) => {
+                self.open.remove(&position.temp_liquidation_account);
+                *self
+                    .closed
+                    .entry(position.temp_liquidation_account.clone())
+                    .or_default() += 1;
+            }
+        }
+    }
+}
+
+/// returns the id of a liquidation event. temp liquidation accounts may be reused by
+/// later liquidations of the same obligation, so the number of previous events which
+/// used the temp account is included, making the id deterministic across restarts
+pub fn liquidation_event_id(temp_account: &str, obligation: &str, sequence: u64) -> String {
+    hashv(&[
+        temp_account.as_bytes(),
+        obligation.as_bytes(),
+        &sequence.to_le_bytes(),
+    ])
+    .to_string()
+}
+
+/// scans the temp liquidation accounts, opening and closing liquidated positions
+/// by comparing the scan against the positions tracked by `tracker`
+pub async fn scrape_liquidated_positions(
+    source: &dyn AccountSource,
+    config: &Arc<Configuration>,
+    sink: &dyn Sink,
+    tracker: &Mutex<LiquidationTracker>,
+    compression: bool,
+) -> ScrapeReport {
+    let mut report = ScrapeReport::new("v1_liquidated_positions");
+    let fetch_start = Utc::now();
+    let accounts = find_temp_liquidation_accounts(source, config, compression).await;
+    report.record_phase("fetch", fetch_start);
+    let accounts = match accounts {
+        Ok(accounts) => accounts,
+        Err(err) => {
+            error!("{:#?}", err);
+            report.set_fatal(FailureReason::Rpc(err.to_string()));
+            return super::finish_report(report);
+        }
+    };
+    let user_farms = match sink.v1_user_farms() {
+        Ok(user_farms) => user_farms,
+        Err(err) => {
+            error!("failed to load user farms {:#?}", err);
+            report.set_fatal(FailureReason::Database(err.to_string()));
+            return super::finish_report(report);
+        }
+    };
+    let owners = super::obligation_owners(&user_farms);
+    let current = accounts
+        .iter()
+        .map(|(key, account)| (*key, account.obligation))
+        .collect::<Vec<_>>();
+    let store_start = Utc::now();
+    let mut tracker = match tracker.lock() {
+        Ok(tracker) => tracker,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    if !tracker.is_seeded() {
+        match sink.v1_liquidated_positions() {
+            Ok(positions) => tracker.seed(&positions),
+            Err(err) => {
+                error!("failed to load liquidated positions {:#?}", err);
+                report.set_fatal(FailureReason::Database(err.to_string()));
+                return super::finish_report(report);
+            }
+        }
+    }
+    for change in tracker.diff(store_start, &current, &owners) {
+        let item_key = ItemKey::Account(change.position().temp_liquidation_account.clone());
+        match sink.put(&change.to_record()) {
+            Ok(outcome) => {
+                match &change {
+                    PositionChange::Opened(position) => info!(
+                        "liquidation {} of obligation {} started",
+                        position.liquidation_event_id, position.obligation
+                    ),
+                    PositionChange::Closed(position, _) => info!(
+                        "liquidation {} of obligation {} ended",
+                        position.liquidation_event_id, position.obligation
+                    ),
+                }
+                tracker.apply(&change);
+                report.record(item_key, outcome.into());
+            }
+            Err(err) => {
+                error!("failed to put liquidated position {:#?}", err);
+                report.record_failure(item_key, FailureReason::Database(err.to_string()));
+            }
+        }
+    }
+    report.record_phase("store", store_start);
+    super::finish_report(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_liquidation_tracker() {
+        let temp_account = Pubkey::new_unique();
+        let obligation = Pubkey::new_unique();
+        let user_farm = V1UserFarm {
+            account_address: "user_farm".to_string(),
+            authority: "authority".to_string(),
+            obligations: vec![obligation.to_string()],
+            obligation_indexes: vec![0],
+            leveraged_farm: "leveraged_farm".to_string(),
+            ..Default::default()
+        };
+        let user_farms = vec![user_farm];
+        let owners = crate::v1::obligation_owners(&user_farms);
+        let mut tracker = LiquidationTracker::default();
+        tracker.seed(&[]);
+
+        let started_at = Utc::now();
+        let changes = tracker.diff(started_at, &[(temp_account, obligation)], &owners);
+        assert_eq!(changes.len(), 1);
+        let opened = match &changes[0] {
+            PositionChange::Opened(position) => position.clone(),
+            _ => panic!("expected position to open"),
+        };
+        assert_eq!(opened.authority, "authority");
+        assert_eq!(opened.user_farm, "user_farm");
+        assert_eq!(opened.leveraged_farm, "leveraged_farm");
+        assert_eq!(
+            opened.liquidation_event_id,
+            liquidation_event_id(&temp_account.to_string(), &obligation.to_string(), 0)
+        );
+        // an unapplied change is produced again by the next scan
+        assert_eq!(
+            tracker.diff(started_at, &[(temp_account, obligation)], &owners),
+            changes
+        );
+        tracker.apply(&changes[0]);
+        assert!(tracker
+            .diff(Utc::now(), &[(temp_account, obligation)], &owners)
+            .is_empty());
+
+        // a restarted tracker resumes the open position
+        let stored = V1LiquidatedPosition {
+            liquidation_event_id: opened.liquidation_event_id.clone(),
+            temp_liquidation_account: opened.temp_liquidation_account.clone(),
+            authority: opened.authority.clone(),
+            user_farm: opened.user_farm.clone(),
+            obligation: opened.obligation.clone(),
+            leveraged_farm: opened.leveraged_farm.clone(),
+            started_at: opened.started_at,
+            ended_at: None,
+            ..Default::default()
+        };
+        let mut restarted = LiquidationTracker::default();
+        restarted.seed(&[stored.clone()]);
+        assert_eq!(restarted.open_positions(), tracker.open_positions());
+
+        let ended_at = Utc::now();
+        let changes = restarted.diff(ended_at, &[], &owners);
+        assert_eq!(
+            changes,
+            vec![PositionChange::Closed(opened.clone(), ended_at)]
+        );
+        restarted.apply(&changes[0]);
+        assert!(restarted.open_positions().is_empty());
+
+        // reusing the temp account for a later liquidation produces a new event id
+        let changes = restarted.diff(Utc::now(), &[(temp_account, obligation)], &owners);
+        assert_eq!(changes.len(), 1);
+        assert_ne!(
+            changes[0].position().liquidation_event_id,
+            opened.liquidation_event_id
+        );
+        let mut reseeded = LiquidationTracker::default();
+        reseeded.seed(&[V1LiquidatedPosition {
+            ended_at: Some(ended_at),
+            ..stored
+        }]);
+        assert_eq!(
+            reseeded.diff(Utc::now(), &[(temp_account, obligation)], &owners)[0]
+                .position()
+                .liquidation_event_id,
+            changes[0].position().liquidation_event_id
+        );
+    }
+}
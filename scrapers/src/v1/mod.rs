@@ -1,164 +1,389 @@
 //! v1 tulip position scrapers
+pub mod liquidations;
+
+use crate::metrics;
+use crate::report::{FailureReason, ItemKey, ItemOutcome, ScrapeReport};
+use crate::scan::{scan_program_accounts, ScanCache, ScanOpts, ScanRequest};
+use crate::sink::{Record, Sink};
 use anchor_client::anchor_lang::AccountDeserialize;
 use anchor_client::anchor_lang::AnchorDeserialize;
+use anchor_client::anchor_lang::Discriminator;
 use anchor_client::solana_sdk::pubkey::Pubkey;
 use anyhow::{anyhow, Result};
 use chrono::prelude::*;
+use common::math::decimal::Decimal;
 use common::tulip::lending_obligation::Obligation;
 use common::v1::accounts::margin::ObligationLiquidationAccount;
 use common::{self, v1::accounts::margin::UserFarm};
 use config::Configuration;
-use crossbeam_channel::{select, tick};
+use db::client::PutOutcome;
+use db::models::V1UserFarm;
 use db::{client, filters::V1UserFarmMatcher};
-use diesel::PgConnection;
-use log::{error, info, warn};
-use solana_account_decoder::UiAccountEncoding;
+use log::{debug, error, info, warn};
+use oracle::rate_lookup::account_source::AccountSource;
+use solana_account_decoder::{UiAccountEncoding, UiDataSliceConfig};
 use solana_client::{
-    rpc_client::RpcClient,
     rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
     rpc_filter::{Memcmp, MemcmpEncodedBytes, MemcmpEncoding, RpcFilterType},
 };
 use solana_sdk::account_info::IntoAccountInfo;
 use solana_sdk::program_pack::Pack;
+use std::collections::HashMap;
 use std::ops::Deref;
-use std::{sync::Arc, time::Duration};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// offset of the owner field within an obligation account
+pub const OBLIGATION_OWNER_OFFSET: usize = 42;
+/// offset of the authority field within a user farm account, following
+/// the anchor account discriminator
+pub const USER_FARM_AUTHORITY_OFFSET: usize = 8;
+/// length of the user farm data used to detect changed user farms, covering every
+/// field following the discriminator. the obligations are stored at the end of
+/// the account, and can be replaced without changing the number of obligations,
+/// so the fingerprint can't stop short of them. as the discriminator is the same
+/// for every user farm, the fingerprint is also enough to decode the user farm
+pub const USER_FARM_FINGERPRINT_LEN: usize =
+    common::v1::accounts::USER_FARM_ACCOUNT_SIZE as usize - USER_FARM_AUTHORITY_OFFSET;
 
 /// does not scrape and calculate obligation ltvs, and instead simply stores
-/// previously unseed obiligation accounts into the database
-pub fn scrape_obligation_accounts(
-    rpc: &Arc<RpcClient>,
+/// previously unseed obiligation accounts into the sink. sliced scans only
+/// fetch the obligation owner, which is the only field that is stored
+pub async fn scrape_obligation_accounts(
+    source: &dyn AccountSource,
     config: &Arc<Configuration>,
-    conn: &PgConnection,
+    sink: &dyn Sink,
     compression: bool,
-) {
-    let db_client = Arc::new(db::client::DBClient {
-        conn: &conn,
-        oob_limit: 10_f64,
-    });
-    let encoding = if compression {
-        Some(UiAccountEncoding::Base64Zstd)
-    } else {
-        Some(UiAccountEncoding::Base64)
+    scan: ScanOpts,
+    cache: &Mutex<ScanCache>,
+) -> ScrapeReport {
+    let mut report = ScrapeReport::new("v1_obligation_accounts");
+    let request = ScanRequest {
+        program_id: config.programs.v1_lending(),
+        filters: vec![RpcFilterType::DataSize(
+            common::v1::accounts::OBLIGATION_ACCOUNT_SIZE as u64,
+        )],
+        encoding: scan_encoding(compression),
+        shard_offset: OBLIGATION_OWNER_OFFSET,
+        slice: UiDataSliceConfig {
+            offset: OBLIGATION_OWNER_OFFSET,
+            length: 32,
+        },
+        fetch_full: false,
     };
-    let lending_id = config.programs.v1_lending();
-    let work_loop = |db_client: &Arc<db::client::DBClient>, lending_id: &Pubkey| match rpc
-        .get_program_accounts_with_config(
-            lending_id,
-            RpcProgramAccountsConfig {
-                filters: Some(vec![RpcFilterType::DataSize(
-                    common::v1::accounts::OBLIGATION_ACCOUNT_SIZE as u64,
-                )]),
-                with_context: None,
-                account_config: RpcAccountInfoConfig {
-                    encoding,
-                    data_slice: None,
-                    commitment: None,
-                },
-            },
-        ) {
-        Ok(mut accounts) => {
-            for (key, account) in accounts.iter_mut() {
-                let key = std::mem::take(key);
-                let account = std::mem::take(account);
-                match Obligation::unpack_unchecked(&account.data[..]) {
-                    Ok(obligation) => {
-                        match db_client.put_v1_obligation_account(
-                            &key.to_string(),
-                            &obligation.owner.to_string(),
-                        ) {
-                            Ok(_) => info!("found new obligation {}", key.to_string()),
-                            Err(_) => (),
-                        }
-                    }
-                    Err(err) => error!("failed to unpack obligation {}: {:#?}", key, err),
+    let fetch_start = Utc::now();
+    let scanned = scan_program_accounts(source, &request, scan, cache).await;
+    report.record_phase("fetch", fetch_start);
+    let scanned = match scanned {
+        Ok(scanned) => {
+            debug!(
+                "fetched {} changed, {} unchanged obligations at slot {}",
+                scanned.changed.len(),
+                scanned.unchanged.len(),
+                scanned.slot
+            );
+            scanned
+        }
+        Err(err) => {
+            error!("failed scrape obligations {:#?}", err);
+            report.set_fatal(FailureReason::Rpc(err.to_string()));
+            return finish_report(report);
+        }
+    };
+    let store_start = Utc::now();
+    for key in scanned.unchanged.iter() {
+        report.record(ItemKey::Account(key.to_string()), ItemOutcome::Skipped);
+    }
+    for scanned in scanned.changed.into_iter() {
+        let key = scanned.key;
+        let item_key = ItemKey::Account(key.to_string());
+        // the fingerprint is the owner of the obligation, regardless of whether the
+        // scan was sliced, so there is no need to unpack the full obligation
+        let owner = match <[u8; 32]>::try_from(&scanned.fingerprint[..]) {
+            Ok(owner) => Pubkey::new_from_array(owner),
+            Err(err) => {
+                error!("failed to decode obligation owner {}: {:#?}", key, err);
+                report.record_failure(item_key, FailureReason::Decode(err.to_string()));
+                continue;
+            }
+        };
+        match sink.put(&Record::V1ObligationAccount {
+            account: key.to_string(),
+            authority: owner.to_string(),
+        }) {
+            Ok(outcome) => {
+                if outcome == PutOutcome::Inserted {
+                    info!("found new obligation {}", key.to_string());
                 }
+                report.record(item_key, outcome.into());
+                lock_cache(cache).record(key, scanned.fingerprint);
+            }
+            Err(err) => {
+                error!("failed to put obligation {}: {:#?}", key, err);
+                report.record_failure(item_key, FailureReason::Database(err.to_string()));
             }
         }
-        Err(err) => error!("failed scrape obligations {:#?}", err),
+    }
+    report.record_phase("store", store_start);
+    finish_report(report)
+}
+
+/// scrapes v1 user farm accounts. every field but the discriminator is fetched in a
+/// single scan, and sliced scans only decode and store new or changed user farms
+pub async fn scrape_user_farm(
+    source: &dyn AccountSource,
+    config: &Arc<Configuration>,
+    sink: &dyn Sink,
+    compression: bool,
+    scan: ScanOpts,
+    cache: &Mutex<ScanCache>,
+) -> ScrapeReport {
+    let mut report = ScrapeReport::new("v1_user_farms");
+    let request = ScanRequest {
+        program_id: config.programs.v1_farm(),
+        filters: vec![RpcFilterType::DataSize(
+            common::v1::accounts::USER_FARM_ACCOUNT_SIZE as u64,
+        )],
+        encoding: scan_encoding(compression),
+        shard_offset: USER_FARM_AUTHORITY_OFFSET,
+        slice: UiDataSliceConfig {
+            offset: USER_FARM_AUTHORITY_OFFSET,
+            length: USER_FARM_FINGERPRINT_LEN,
+        },
+        fetch_full: false,
     };
-    let start = Utc::now();
-    work_loop(&db_client, &lending_id);
-    let time_took = Utc::now().signed_duration_since(start);
-    info!(
-        "obligation scraping took {} seconds",
-        time_took.num_seconds()
-    );
+    let fetch_start = Utc::now();
+    let scanned = scan_program_accounts(source, &request, scan, cache).await;
+    report.record_phase("fetch", fetch_start);
+    let scanned = match scanned {
+        Ok(scanned) => {
+            debug!(
+                "fetched {} changed, {} unchanged user farms at slot {}",
+                scanned.changed.len(),
+                scanned.unchanged.len(),
+                scanned.slot
+            );
+            scanned
+        }
+        Err(err) => {
+            error!("failed to scrape user farms {:#?}", err);
+            report.set_fatal(FailureReason::Rpc(err.to_string()));
+            return finish_report(report);
+        }
+    };
+    let store_start = Utc::now();
+    for key in scanned.unchanged.iter() {
+        report.record(ItemKey::Account(key.to_string()), ItemOutcome::Skipped);
+    }
+    for scanned in scanned.changed.into_iter() {
+        let key = scanned.key;
+        let item_key = ItemKey::Account(key.to_string());
+        match decode_user_farm(&scanned.fingerprint) {
+            Ok(user_farm) => {
+                let mut obligations = Vec::with_capacity(3);
+                let mut obligation_indexes = Vec::with_capacity(3);
+                for i in 0..user_farm.obligations.len() {
+                    if user_farm.obligations[i].obligation_account != common::DEFAULT_KEY {
+                        obligations.push(user_farm.obligations[i].obligation_account.to_string());
+                        obligation_indexes.push(i as i32);
+                    }
+                }
+                match sink.put(&Record::V1UserFarm {
+                    authority: user_farm.authority.to_string(),
+                    account_address: key.to_string(),
+                    leveraged_farm: user_farm.leveraged_farm.to_string(),
+                    obligations,
+                    obligation_indexes,
+                }) {
+                    Ok(outcome) => {
+                        report.record(item_key, outcome.into());
+                        lock_cache(cache).record(key, scanned.fingerprint);
+                    }
+                    Err(err) => {
+                        error!("failed to put user farm {}: {:#?}", key, err);
+                        report.record_failure(item_key, FailureReason::Database(err.to_string()));
+                    }
+                }
+            }
+            Err(err) => {
+                error!("failed to deserialize userfarm {}: {:#?}", key, err);
+                report.record_failure(item_key, FailureReason::Decode(err.to_string()));
+            }
+        }
+    }
+    report.record_phase("store", store_start);
+    finish_report(report)
 }
 
-pub fn scrape_user_farm(
-    rpc: &Arc<RpcClient>,
+fn scan_encoding(compression: bool) -> Option<UiAccountEncoding> {
+    if compression {
+        Some(UiAccountEncoding::Base64Zstd)
+    } else {
+        Some(UiAccountEncoding::Base64)
+    }
+}
+
+/// decodes a user farm from its fingerprint, restoring the discriminator which
+/// isn't part of the scanned slice
+fn decode_user_farm(fingerprint: &[u8]) -> std::io::Result<UserFarm> {
+    let mut data = Vec::with_capacity(USER_FARM_AUTHORITY_OFFSET + fingerprint.len());
+    data.extend_from_slice(&UserFarm::discriminator());
+    data.extend_from_slice(fingerprint);
+    UserFarm::deserialize(&mut &data[..])
+}
+
+fn lock_cache(cache: &Mutex<ScanCache>) -> MutexGuard<'_, ScanCache> {
+    match cache.lock() {
+        Ok(cache) => cache,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// computes the ltv of every obligation, storing it alongside the user farm and
+/// leveraged farm which own the obligation. owners are resolved from the user farms
+/// previously stored by `scrape_user_farm`, so obligations belonging to user farms
+/// which have not been scraped yet are reported as unresolved
+pub async fn scrape_obligation_ltvs(
+    source: &dyn AccountSource,
     config: &Arc<Configuration>,
-    conn: &PgConnection,
+    sink: &dyn Sink,
     compression: bool,
-) {
-    let db_client = Arc::new(db::client::DBClient {
-        conn: &conn,
-        oob_limit: 10_f64,
-    });
-    let farm_id = config.programs.v1_farm();
+) -> ScrapeReport {
+    let mut report = ScrapeReport::new("v1_obligation_ltvs");
+    let user_farms = match sink.v1_user_farms() {
+        Ok(user_farms) => user_farms,
+        Err(err) => {
+            error!("failed to load user farms {:#?}", err);
+            report.set_fatal(FailureReason::Database(err.to_string()));
+            return finish_report(report);
+        }
+    };
+    let owners = obligation_owners(&user_farms);
     let encoding = if compression {
         Some(UiAccountEncoding::Base64Zstd)
     } else {
         Some(UiAccountEncoding::Base64)
     };
-    let work_loop = |db_client: &Arc<db::client::DBClient>, farm_id: &Pubkey| match rpc
-        .get_program_accounts_with_config(
-            farm_id,
+    let lending_id = config.programs.v1_lending();
+    let commitment = source.commitment();
+    let fetch_start = Utc::now();
+    let accounts = source
+        .get_program_accounts(
+            &lending_id,
             RpcProgramAccountsConfig {
                 filters: Some(vec![RpcFilterType::DataSize(
-                    common::v1::accounts::USER_FARM_ACCOUNT_SIZE as u64,
+                    common::v1::accounts::OBLIGATION_ACCOUNT_SIZE as u64,
                 )]),
-                with_context: None,
+                with_context: Some(true),
                 account_config: RpcAccountInfoConfig {
                     encoding,
                     data_slice: None,
-                    commitment: None,
+                    commitment: Some(commitment),
                 },
             },
-        ) {
-        Ok(mut accounts) => {
-            for (key, account) in accounts.iter_mut() {
-                let key = std::mem::take(key);
-                let account = std::mem::take(account);
-                match UserFarm::deserialize(&mut &account.data[..]) {
-                    Ok(user_farm) => {
-                        let mut obligations = Vec::with_capacity(3);
-                        let mut obligation_indexes = Vec::with_capacity(3);
-                        for i in 0..user_farm.obligations.len() {
-                            if user_farm.obligations[i].obligation_account != common::DEFAULT_KEY {
-                                obligations
-                                    .push(user_farm.obligations[i].obligation_account.to_string());
-                                obligation_indexes.push(i as i32);
-                            }
-                        }
-                        match db_client.put_v1_user_farm(
-                            &user_farm.authority.to_string(),
-                            &key.to_string(),
-                            &user_farm.leveraged_farm.to_string(),
-                            &obligations,
-                            &obligation_indexes,
-                        ) {
-                            Ok(_) => (),
-                            Err(err) => error!("failed to put user farm {}: {:#?}", key, err),
-                        }
-                    }
-                    Err(err) => error!("failed to deserialize userfarm {}: {:#?}", key, err),
-                }
-            }
+        )
+        .await;
+    report.record_phase("fetch", fetch_start);
+    let (slot, accounts) = match accounts {
+        Ok(accounts) => accounts,
+        Err(err) => {
+            error!("failed to scrape obligations {:#?}", err);
+            report.set_fatal(FailureReason::Rpc(err.to_string()));
+            return finish_report(report);
         }
-        Err(err) => error!("failed to scrape user farms {:#?}", err),
     };
-    let start = Utc::now();
-    work_loop(&db_client, &farm_id);
-    let time_took = Utc::now().signed_duration_since(start);
-    info!(
-        "user farm scraping took {} seconds",
-        time_took.num_seconds()
-    );
+    let commitment = commitment.commitment.to_string();
+    let store_start = Utc::now();
+    for (key, account) in accounts.iter() {
+        let item_key = ItemKey::Account(key.to_string());
+        let obligation = match Obligation::unpack_unchecked(&account.data[..]) {
+            Ok(obligation) => obligation,
+            Err(err) => {
+                error!("failed to unpack obligation {}: {:#?}", key, err);
+                report.record_failure(item_key, FailureReason::Decode(err.to_string()));
+                continue;
+            }
+        };
+        let ltv = match obligation_ltv(&obligation) {
+            Ok(ltv) => ltv,
+            Err(err) => {
+                error!("failed to compute ltv for obligation {}: {:#?}", key, err);
+                report.record_failure(item_key, FailureReason::Compute(err.to_string()));
+                continue;
+            }
+        };
+        let account_address = key.to_string();
+        let user_farm = match owners.get(account_address.as_str()) {
+            Some(user_farm) => user_farm,
+            None => {
+                warn!("no user farm found for obligation {}", key);
+                report.record_failure(
+                    item_key,
+                    FailureReason::Unresolved(format!(
+                        "user farm for obligation owned by {}",
+                        obligation.owner
+                    )),
+                );
+                continue;
+            }
+        };
+        match sink.put(&Record::V1ObligationLtv {
+            authority: user_farm.authority.clone(),
+            user_farm: user_farm.account_address.clone(),
+            account_address,
+            leveraged_farm: user_farm.leveraged_farm.clone(),
+            ltv,
+            scraped_at: store_start,
+            slot,
+            commitment: commitment.clone(),
+        }) {
+            Ok(outcome) => report.record(item_key, outcome.into()),
+            Err(err) => {
+                error!("failed to put obligation ltv {}: {:#?}", key, err);
+                report.record_failure(item_key, FailureReason::Database(err.to_string()));
+            }
+        }
+    }
+    report.record_phase("store", store_start);
+    finish_report(report)
+}
+
+/// maps obligation address -> the user farm which owns the obligation
+pub fn obligation_owners(user_farms: &[V1UserFarm]) -> HashMap<&str, &V1UserFarm> {
+    user_farms
+        .iter()
+        .flat_map(|user_farm| {
+            user_farm
+                .obligations
+                .iter()
+                .map(move |obligation| (obligation.as_str(), user_farm))
+        })
+        .collect()
+}
+
+/// returns the ratio of the obligation's borrowed value to its deposited value. unused
+/// obligations have nothing deposited or borrowed, and are treated as having an ltv of 0
+pub fn obligation_ltv(obligation: &Obligation) -> Result<f64> {
+    if obligation.deposits_market_value == Decimal::zero()
+        && obligation.borrowed_value == Decimal::zero()
+    {
+        return Ok(0_f64);
+    }
+    let ltv = obligation
+        .loan_to_value()
+        .map_err(|err| anyhow!("failed to compute ltv {:?}", err))?;
+    Ok(ltv.to_f64())
+}
+
+fn finish_report(report: ScrapeReport) -> ScrapeReport {
+    let report = report.finish();
+    metrics::observe_report(&report);
+    info!("{}", report);
+    report
 }
 
-pub fn find_temp_liquidation_accounts(
-    rpc: &RpcClient,
+pub async fn find_temp_liquidation_accounts(
+    source: &dyn AccountSource,
     config: &Configuration,
     compression: bool,
 ) -> Result<Vec<(Pubkey, ObligationLiquidationAccount)>> {
@@ -168,19 +393,22 @@ pub fn find_temp_liquidation_accounts(
     } else {
         Some(UiAccountEncoding::Base64)
     };
-    match rpc.get_program_accounts_with_config(
-        &farm_id,
-        RpcProgramAccountsConfig {
-            filters: Some(vec![RpcFilterType::DataSize(345 as u64)]),
-            with_context: None,
-            account_config: RpcAccountInfoConfig {
-                encoding,
-                data_slice: None,
-                commitment: None,
+    match source
+        .get_program_accounts(
+            &farm_id,
+            RpcProgramAccountsConfig {
+                filters: Some(vec![RpcFilterType::DataSize(345 as u64)]),
+                with_context: Some(true),
+                account_config: RpcAccountInfoConfig {
+                    encoding,
+                    data_slice: None,
+                    commitment: Some(source.commitment()),
+                },
             },
-        },
-    ) {
-        Ok(mut accounts) => {
+        )
+        .await
+    {
+        Ok((_, mut accounts)) => {
             let mut response = Vec::with_capacity(accounts.len());
             for (key, account) in accounts.iter_mut() {
                 let key = std::mem::take(key);
@@ -202,3 +430,20 @@ pub fn find_temp_liquidation_accounts(
         )),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_obligation_ltv() {
+        let mut obligation = Obligation::default();
+        assert_eq!(obligation_ltv(&obligation).unwrap(), 0_f64);
+        obligation.deposits_market_value = Decimal::from(200_u64);
+        obligation.borrowed_value = Decimal::from(50_u64);
+        assert_eq!(obligation_ltv(&obligation).unwrap(), 0.25);
+        // borrowing without any deposits can not be represented as a ratio
+        obligation.deposits_market_value = Decimal::zero();
+        assert!(obligation_ltv(&obligation).is_err());
+    }
+}